@@ -0,0 +1,146 @@
+use std::cmp;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use parity_crypto::publickey::{KeyPair, Public};
+
+use rayon::prelude::*;
+
+use crate::checkpoint;
+use crate::checkpoint::PendingMatch;
+use crate::client::Client;
+use crate::config;
+use crate::errors::Error;
+use crate::key;
+use crate::scan;
+use crate::scan::ScanTarget;
+use crate::source::AnnouncementSource;
+
+pub fn backfill(
+    storage_dir: &PathBuf,
+    master_address: &str,
+    passphrase: &str,
+    from_block: Option<u64>,
+    to_block: Option<u64>,
+    confirmations: u64,
+    chunk_size: u64,
+    config_file: &Option<PathBuf>,
+    source: Box<dyn AnnouncementSource>
+) -> Result<(), Error> {
+    // empty chunks would never move the checkpoint
+    if chunk_size == 0 {
+        return Err(Error::Custom(String::from("The chunk size must be at least 1 block")));
+    }
+    let config = config::load(&config_file)?;
+    let client = Client::new()?;
+    let source = source.as_ref();
+
+    let master_address = master_address.to_lowercase();
+    let _checkpoint_lock = checkpoint::lock(&storage_dir, &master_address)?;
+
+    // load only the scan keypair and the spend public key
+    let scan_keypair = key::load_scan(&storage_dir, &master_address, &passphrase)?;
+    let spend_public_key = key::load_public(&storage_dir, &master_address)?;
+
+    let mut checkpoint = checkpoint::resume(&storage_dir, &master_address, from_block)?
+        .ok_or_else(|| Error::Custom(String::from("No scan checkpoint to backfill from, pass a start block")))?;
+    if let Some(rolled_back) = scan::rollback(&client, &storage_dir, &master_address, &checkpoint, source)? {
+        checkpoint = rolled_back;
+    }
+
+    // never past the confirmed head
+    let confirmed_block = source.head(&client)?.saturating_sub(source.confirmations(confirmations));
+    let end_block = cmp::min(to_block.unwrap_or(confirmed_block), confirmed_block);
+    let start_block = checkpoint.block;
+    let started_at = Instant::now();
+    let mut matches = 0;
+
+    // one chunk per core at a time
+    let width = rayon::current_num_threads() as u64;
+    while checkpoint.block < end_block {
+        let chunks: Vec<(u64, u64)> = (0..width)
+            .map(|i| checkpoint.block + i * chunk_size)
+            .take_while(|chunk_gt| *chunk_gt < end_block)
+            .map(|chunk_gt| (chunk_gt, cmp::min(chunk_gt + chunk_size, end_block)))
+            .collect();
+        let results: Vec<Result<Vec<PendingMatch>, Error>> = chunks
+            .par_iter()
+            .map(|(chunk_gt, chunk_lte)| scan_chunk(source, *chunk_gt, *chunk_lte, &scan_keypair, &spend_public_key))
+            .collect();
+
+        // the checkpoint only moves over the completed chunks
+        // right after it, along with the matches of those chunks
+        let mut next = checkpoint.clone();
+        let mut failure = None;
+        for ((_, chunk_lte), result) in chunks.iter().zip(results) {
+            match result {
+                Ok(chunk_matches) => {
+                    matches += chunk_matches.len();
+                    if let Some(last_match) = chunk_matches.last() {
+                        next.last_match = Some(last_match.announcement.clone());
+                    }
+                    next.pending.extend(chunk_matches);
+                    next.block = *chunk_lte;
+                },
+                Err(e) => {
+                    failure = Some(e);
+                    break;
+                }
+            }
+        }
+        if next.block > checkpoint.block {
+            let block_hash = source.block_hash(&client, next.block)?;
+            next.advance(next.block, block_hash);
+            checkpoint::store(&storage_dir, &master_address, &next)?;
+            checkpoint = next;
+        }
+        if let Some(e) = failure {
+            return Err(e);
+        }
+
+        let elapsed = started_at.elapsed().as_secs_f64().max(0.001);
+        println!(
+            "[backfill] Block {} of {}, {:.0} blocks/s, {} matches",
+            checkpoint.block,
+            end_block,
+            (checkpoint.block - start_block) as f64 / elapsed,
+            matches
+        );
+    }
+
+    // handle the matches right away, the ones
+    // left unhandled are retried by the daemon
+    if !checkpoint.pending.is_empty() {
+        let mut target = ScanTarget {
+            master_address: master_address,
+            scan_keypair: scan_keypair,
+            spend_public_key: spend_public_key,
+            checkpoint: checkpoint
+        };
+        scan::handle_matches(&client, &storage_dir, &passphrase, &mut target, &config);
+        checkpoint::store(&storage_dir, &target.master_address, &target.checkpoint)?;
+    }
+
+    Ok(())
+}
+
+fn scan_chunk(
+    source: &dyn AnnouncementSource,
+    block_gt: u64,
+    block_lte: u64,
+    scan_keypair: &KeyPair,
+    spend_public_key: &Public
+) -> Result<Vec<PendingMatch>, Error> {
+    // node clients are not shared between threads
+    let client = Client::new()?;
+    let txs = source.announcements(&client, block_gt, block_lte)?;
+
+    // trial decryption on all cores
+    let my_txs = txs
+        .par_iter()
+        .filter_map(|tx| scan::is_my_tx(tx, &scan_keypair, &spend_public_key).ok())
+        .map(|tx| PendingMatch::from_tx(&tx))
+        .collect();
+
+    Ok(my_txs)
+}
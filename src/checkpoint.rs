@@ -0,0 +1,229 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use web3::types::H256;
+
+use crate::errors::Error;
+use crate::index::Announcement;
+use crate::notify::NotificationSink;
+use crate::query::RsTxTransaction;
+use crate::scheme::StealthScheme;
+use crate::sweep::PendingSweep;
+
+// not a `.json` file, so that it is never
+// mistaken for a key file in the storage dir
+static CHECKPOINT_EXTENSION: &'static str = "checkpoint";
+
+// number of processed block hashes kept to detect reorgs
+const REORG_WINDOW: usize = 64;
+
+/// Scan progress of a master key
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Checkpoint {
+    /// Last fully processed block
+    pub block: u64,
+    /// Hashes of the recently processed blocks
+    #[serde(default)]
+    pub block_hashes: Vec<BlockHash>,
+    /// Matches up to `block` that are not handled yet
+    pub pending: Vec<PendingMatch>,
+    /// Announcement of the last detected payment
+    #[serde(default)]
+    pub last_match: Option<Announcement>,
+    /// Claimed payments waiting to be swept
+    #[serde(default)]
+    pub sweeps: Vec<PendingSweep>,
+}
+
+/// Announcement detected as a payment to the master key
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingMatch {
+    pub nonce_point: String,
+    pub scheme: StealthScheme,
+    pub announcement: Announcement,
+    /// Sinks already notified of the payment
+    #[serde(default)]
+    pub notified: Vec<NotificationSink>,
+    /// Failed attempts at notifying the other sinks
+    #[serde(default)]
+    pub attempts: u32,
+    /// Unix time before which the match is not retried
+    #[serde(default)]
+    pub retry_at: u64,
+}
+
+impl PendingMatch {
+    pub fn from_tx(tx: &RsTxTransaction) -> PendingMatch {
+        PendingMatch {
+            nonce_point: tx.nonce_point.clone(),
+            scheme: tx.scheme,
+            announcement: Announcement {
+                tx_hash: tx.id.clone(),
+                block: tx.block.as_u64()
+            },
+            notified: vec![],
+            attempts: 0,
+            retry_at: 0
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockHash {
+    pub number: u64,
+    pub hash: H256,
+}
+
+impl Checkpoint {
+    /// Moves the checkpoint forward, recording the
+    /// block hash (if known) in the recent window
+    pub fn advance(&mut self, block: u64, hash: Option<H256>) {
+        self.block = block;
+        if let Some(hash) = hash {
+            self.block_hashes.push(BlockHash { number: block, hash: hash });
+            let excess = self.block_hashes.len().saturating_sub(REORG_WINDOW);
+            self.block_hashes.drain(..excess);
+        }
+    }
+
+    /// Moves the checkpoint back after a reorg, dropping the
+    /// block hashes and pending matches of orphaned blocks
+    pub fn rollback(&mut self, block: u64) {
+        self.block = block;
+        self.block_hashes.retain(|block_hash| block_hash.number <= block);
+        self.pending.retain(|pending_match| pending_match.announcement.block <= block);
+        self.sweeps.retain(|pending_sweep| pending_sweep.announcement.block <= block);
+    }
+}
+
+fn checkpoint_path(storage_dir: &PathBuf, master_address: &str) -> PathBuf {
+    let mut path = storage_dir.clone();
+    path.push(format!("{}.{}", master_address.to_lowercase(), CHECKPOINT_EXTENSION));
+    path
+}
+
+pub fn load(storage_dir: &PathBuf, master_address: &str) -> Result<Option<Checkpoint>, Error> {
+    let path = checkpoint_path(&storage_dir, &master_address);
+
+    // a master key that was never scanned has no checkpoint
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = File::open(path)?;
+    let checkpoint: Checkpoint = serde_json::from_reader(file)?;
+
+    Ok(Some(checkpoint))
+}
+
+/// Checkpoint to resume scanning from, the stored one unless a start
+/// block is given explicitly (only its pending matches are kept then)
+pub fn resume(storage_dir: &PathBuf, master_address: &str, from_block: Option<u64>) -> Result<Option<Checkpoint>, Error> {
+    let stored = load(&storage_dir, &master_address)?;
+    let checkpoint = match (from_block, stored) {
+        (Some(b), stored) => {
+            let mut checkpoint = stored.unwrap_or_default();
+            checkpoint.block = b;
+            checkpoint.block_hashes.clear();
+            Some(checkpoint)
+        },
+        (None, stored) => stored
+    };
+
+    Ok(checkpoint)
+}
+
+/// Stores the checkpoint along with its pending matches
+/// in a single write, so both are committed together
+pub fn store(storage_dir: &PathBuf, master_address: &str, checkpoint: &Checkpoint) -> Result<(), Error> {
+    let path = checkpoint_path(&storage_dir, &master_address);
+
+    // write to a temporary file first, so that a crash
+    // never leaves a truncated checkpoint behind
+    let tmp_path = path.with_extension("checkpoint.tmp");
+    let mut file = File::create(&tmp_path)?;
+    write!(file, "{}", serde_json::to_string_pretty(&checkpoint)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
+
+    Ok(())
+}
+
+/// Exclusive lock on the checkpoint of a master key, so
+/// that a scanner and a backfill never write it both
+pub struct CheckpointLock {
+    _file: File,
+}
+
+/// Locks the checkpoint until the returned lock is dropped,
+/// fails right away if another process holds it
+pub fn lock(storage_dir: &PathBuf, master_address: &str) -> Result<CheckpointLock, Error> {
+    let path = checkpoint_path(&storage_dir, &master_address).with_extension("checkpoint.lock");
+    let file = OpenOptions::new().create(true).write(true).open(&path)?;
+
+    // released by the OS when the file is closed,
+    // so a crashed process never keeps it
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return Err(Error::Custom(format!(
+            "The checkpoint of {} is in use by another scan or backfill",
+            master_address
+        )));
+    }
+
+    Ok(CheckpointLock { _file: file })
+}
+
+pub fn reset(storage_dir: &PathBuf, master_address: &str) -> Result<(), Error> {
+    let path = checkpoint_path(&storage_dir, &master_address);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_load_and_reset() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        assert!(load(&storage_dir, "0xAA").unwrap().is_none());
+
+        let pending_match = PendingMatch {
+            nonce_point: String::from("02cc"),
+            scheme: StealthScheme::Eip5564,
+            announcement: Announcement { tx_hash: String::from("0xabcd"), block: 42 },
+            notified: vec![NotificationSink::Command(String::from("true"))],
+            attempts: 1,
+            retry_at: 0
+        };
+        let mut checkpoint = Checkpoint::default();
+        checkpoint.advance(40, Some(H256::from_low_u64_be(40)));
+        checkpoint.advance(42, Some(H256::from_low_u64_be(42)));
+        checkpoint.pending.push(pending_match.clone());
+        store(&storage_dir, "0xAA", &checkpoint).unwrap();
+
+        let mut loaded = load(&storage_dir, "0xaa").unwrap().unwrap();
+        assert!(loaded.block == 42 && loaded.block_hashes.len() == 2);
+        assert!(loaded.pending == vec![pending_match]);
+
+        loaded.rollback(41);
+        assert!(loaded.block == 41 && loaded.block_hashes.len() == 1);
+        assert!(loaded.pending.is_empty());
+
+        reset(&storage_dir, "0xaa").unwrap();
+        assert!(load(&storage_dir, "0xAA").unwrap().is_none());
+
+        let checkpoint_lock = lock(&storage_dir, "0xAA").unwrap();
+        assert!(lock(&storage_dir, "0xaa").is_err());
+        drop(checkpoint_lock);
+        assert!(lock(&storage_dir, "0xaa").is_ok());
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use web3::Web3;
+use web3::futures::{Future, Stream};
+use web3::transports::{Batch, EventLoopHandle, Http, WebSocket};
+use web3::types::{BlockId, H160, H256, TransactionId, U256};
+
+use crate::errors::Error;
+
+static NODE_URL: &'static str = "http://127.0.0.1:8545";
+
+// delay before subscribing again after the
+// WebSocket connection to the node is lost
+const RESUBSCRIBE_DELAY_SECS: u64 = 10;
+
+/// Node and subgraph clients shared by the scanner. Instantiate it
+/// after daemonizing, its event loop does not survive a fork
+pub struct Client {
+    _eloop: EventLoopHandle,
+    transport: Http,
+    pub web3: Web3<Http>,
+    /// HTTP client for the subgraph and webhooks
+    pub http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new() -> Result<Client, Error> {
+        let (eloop, transport) = Http::new(NODE_URL)?;
+        let web3 = Web3::new(transport.clone());
+
+        Ok(Client {
+            _eloop: eloop,
+            transport: transport,
+            web3: web3,
+            http: reqwest::Client::new()
+        })
+    }
+
+    /// Balances of the addresses, in a single batched request
+    pub fn balances(&self, addresses: &[H160]) -> Result<Vec<U256>, Error> {
+        if addresses.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let batch = Web3::new(Batch::new(self.transport.clone()));
+        for address in addresses {
+            // the responses are read from the submitted batch
+            let _ = batch.eth().balance(*address, None);
+        }
+        let responses = batch.transport().submit_batch().wait()?;
+
+        let mut balances = vec![];
+        for response in responses {
+            let balance = serde_json::from_value(response?)?;
+            balances.push(balance);
+        }
+
+        Ok(balances)
+    }
+
+    /// Tx that funded the address along with its announcement: the
+    /// announcement tx itself when it carries the value, or else a tx
+    /// of the announcing account in the same block. None if neither did
+    pub fn funding_tx(&self, address: H160, announcement_tx: H256) -> Result<Option<H256>, Error> {
+        let announcement = match self.web3.eth().transaction(TransactionId::Hash(announcement_tx)).wait()? {
+            Some(announcement) => announcement,
+            None => return Ok(None)
+        };
+        if announcement.to == Some(address) && !announcement.value.is_zero() {
+            return Ok(Some(announcement.hash));
+        }
+
+        let block_hash = match announcement.block_hash {
+            Some(block_hash) => block_hash,
+            None => return Ok(None)
+        };
+        let block = self.web3.eth().block_with_txs(BlockId::Hash(block_hash)).wait()?;
+        let funding_tx = block.and_then(|block| {
+            block.transactions
+                .iter()
+                .find(|tx| tx.from == announcement.from && tx.to == Some(address) && !tx.value.is_zero())
+                .map(|tx| tx.hash)
+        });
+
+        Ok(funding_tx)
+    }
+}
+
+/// Numbers of the new chain heads, pushed by the node
+/// (`eth_subscribe("newHeads")`) from a background thread,
+/// which subscribes again whenever the connection is lost
+pub fn subscribe_new_heads(ws_url: &str) -> Receiver<u64> {
+    let (sender, receiver) = mpsc::channel();
+    let ws_url = ws_url.to_string();
+
+    thread::spawn(move || {
+        loop {
+            match forward_new_heads(&ws_url, &sender) {
+                // the receiving end is gone
+                Ok(_) => return,
+                Err(e) => eprintln!("[scan] New heads subscription lost: {:?}", e)
+            }
+            thread::sleep(Duration::from_secs(RESUBSCRIBE_DELAY_SECS));
+        }
+    });
+
+    receiver
+}
+
+fn forward_new_heads(ws_url: &str, sender: &Sender<u64>) -> Result<(), Error> {
+    let (_eloop, transport) = WebSocket::new(ws_url)?;
+    let web3 = Web3::new(transport);
+    let subscription = web3.eth_subscribe().subscribe_new_heads().wait()?;
+
+    for header in subscription.wait() {
+        if let Some(number) = header?.number {
+            if sender.send(number.as_u64()).is_err() {
+                return Ok(());
+            }
+        }
+    }
+
+    Err(Error::Custom(String::from("Subscription stream ended")))
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Error;
+use crate::notify::NotificationSink;
+use crate::sweep::SweepPolicy;
+
+/// Scan settings of the `--config` file (JSON),
+/// reloaded by the scanner on SIGHUP
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct ScanConfig {
+    /// Overrides `--confirmations`
+    pub confirmations: Option<u64>,
+    /// Overrides `--error-budget`
+    pub error_budget: Option<u32>,
+    /// Labels of the master keys, by address
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// Sinks every detected payment is reported to
+    #[serde(default)]
+    pub notifications: Vec<NotificationSink>,
+    /// Auto-sweep policies of the master keys, by address
+    #[serde(default)]
+    pub sweep: HashMap<String, SweepPolicy>,
+}
+
+impl ScanConfig {
+    /// Label of the master key, its address if it has none
+    pub fn label(&self, master_address: &str) -> String {
+        by_address(&self.labels, &master_address)
+            .cloned()
+            .unwrap_or_else(|| master_address.to_string())
+    }
+
+    pub fn sweep_policy(&self, master_address: &str) -> Option<&SweepPolicy> {
+        by_address(&self.sweep, &master_address)
+    }
+}
+
+fn by_address<'a, T>(map: &'a HashMap<String, T>, master_address: &str) -> Option<&'a T> {
+    map.iter()
+        .find(|(address, _)| address.to_lowercase() == master_address.to_lowercase())
+        .map(|(_, value)| value)
+}
+
+pub fn load(config_file: &Option<PathBuf>) -> Result<ScanConfig, Error> {
+    let config_file = match config_file {
+        Some(config_file) => config_file,
+        None => return Ok(ScanConfig::default())
+    };
+    let file = File::open(config_file)?;
+    let config: ScanConfig = serde_json::from_reader(file)?;
+
+    // a bad sweep policy fails when loading, not when sweeping
+    for policy in config.sweep.values() {
+        policy.destination()?;
+        policy.min_value()?;
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        assert!(load(&None).unwrap().confirmations.is_none());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config_file = temp_dir.path().join("config.json");
+        std::fs::write(&config_file, r#"{"confirmations": 3, "labels": {"0xAA": "shop"}, "notifications": [{"jsonl": "payments.jsonl"}]}"#).unwrap();
+
+        let config = load(&Some(config_file.clone())).unwrap();
+        assert!(config.confirmations == Some(3) && config.error_budget.is_none());
+        assert!(config.label("0xaa") == "shop" && config.label("0xbb") == "0xbb");
+        assert!(config.notifications.len() == 1 && config.sweep_policy("0xaa").is_none());
+
+        std::fs::write(&config_file, r#"{"sweep": {"0xAA": {"destination": "0x01"}}}"#).unwrap();
+        assert!(load(&Some(config_file.clone())).is_err());
+    }
+}
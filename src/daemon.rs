@@ -0,0 +1,185 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Error;
+use crate::health::Health;
+use crate::index::Announcement;
+use crate::scan::ScanTarget;
+use crate::utils::unix_timestamp;
+
+// time given to the daemon to exit after SIGTERM
+const STOP_TIMEOUT_SECS: u64 = 30;
+
+/// Files of the scan daemon, by default in the storage dir
+#[derive(Clone, Debug)]
+pub struct DaemonPaths {
+    pub pid_file: PathBuf,
+    pub log_dir: PathBuf,
+    pub socket: PathBuf,
+}
+
+impl DaemonPaths {
+    pub fn new(
+        storage_dir: &PathBuf,
+        pid_file: Option<PathBuf>,
+        log_dir: Option<PathBuf>,
+        socket: Option<PathBuf>
+    ) -> DaemonPaths {
+        DaemonPaths {
+            pid_file: pid_file.unwrap_or_else(|| storage_dir.join("scan.pid")),
+            log_dir: log_dir.unwrap_or_else(|| storage_dir.join("logs")),
+            socket: socket.unwrap_or_else(|| storage_dir.join("scan.sock"))
+        }
+    }
+
+    pub fn stdout(&self) -> PathBuf {
+        self.log_dir.join("scan.out")
+    }
+
+    pub fn stderr(&self) -> PathBuf {
+        self.log_dir.join("scan.err")
+    }
+}
+
+/// Status report of a running scan daemon
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScanStatus {
+    pub pid: u32,
+    /// Unix timestamp of the daemon start
+    pub started_at: u64,
+    pub health: String,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub keys: Vec<KeyStatus>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KeyStatus {
+    pub master_address: String,
+    /// Last fully processed block
+    pub last_block: u64,
+    pub pending: usize,
+    pub last_match: Option<Announcement>,
+}
+
+impl ScanStatus {
+    pub fn new() -> ScanStatus {
+        ScanStatus {
+            pid: std::process::id(),
+            started_at: unix_timestamp(),
+            health: String::from("starting"),
+            consecutive_failures: 0,
+            last_error: None,
+            keys: vec![]
+        }
+    }
+
+    pub fn update(&mut self, health: &Health, targets: &[ScanTarget]) {
+        self.health = health.state.to_string();
+        self.consecutive_failures = health.consecutive_failures;
+        self.last_error = health.last_error.clone();
+        self.keys = targets
+            .iter()
+            .map(|target| KeyStatus {
+                master_address: target.master_address.clone(),
+                last_block: target.checkpoint.block,
+                pending: target.checkpoint.pending.len(),
+                last_match: target.checkpoint.last_match.clone()
+            })
+            .collect();
+    }
+}
+
+/// Answers every connection on the status socket
+/// with the current status, from a background thread
+pub fn serve_status(socket: &PathBuf, status: Arc<Mutex<ScanStatus>>) -> Result<(), Error> {
+    // a socket left behind by a daemon that did not exit cleanly
+    if socket.exists() {
+        fs::remove_file(&socket)?;
+    }
+    let listener = UnixListener::bind(&socket)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let report = stream.map_err(Error::from).and_then(|stream| {
+                let status = status.lock().map_err(|_| Error::Custom(String::from("Poisoned status lock")))?;
+                serde_json::to_writer(stream, &*status)?;
+                Ok(())
+            });
+            if let Err(e) = report {
+                eprintln!("[scan] Error reporting status: {:?}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+pub fn status(paths: &DaemonPaths) -> Result<ScanStatus, Error> {
+    let stream = UnixStream::connect(&paths.socket)
+        .map_err(|_| Error::Custom(format!("No scan daemon listening on {:?}", paths.socket)))?;
+    let status: ScanStatus = serde_json::from_reader(stream)?;
+
+    Ok(status)
+}
+
+/// Stops the daemon, returns its pid, or none if
+/// only a stale pid file of a dead daemon was left
+pub fn stop(paths: &DaemonPaths) -> Result<Option<u32>, Error> {
+    let pid_str = fs::read_to_string(&paths.pid_file)
+        .map_err(|_| Error::Custom(format!("No pid file at {:?}", paths.pid_file)))?;
+    let pid: i32 = pid_str
+        .trim()
+        .parse()
+        .map_err(|_| Error::Custom(format!("Invalid pid file at {:?}", paths.pid_file)))?;
+
+    // 0 and negative pids signal whole process groups
+    if pid <= 0 {
+        return Err(Error::Custom(format!("Invalid pid {} in {:?}", pid, paths.pid_file)));
+    }
+
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ESRCH) {
+            let _ = fs::remove_file(&paths.pid_file);
+            let _ = fs::remove_file(&paths.socket);
+            return Ok(None);
+        }
+        return Err(Error::Io(error));
+    }
+
+    // wait for the daemon to exit, then clean up after it
+    for _ in 0..STOP_TIMEOUT_SECS * 10 {
+        if unsafe { libc::kill(pid, 0) } != 0 {
+            let _ = fs::remove_file(&paths.pid_file);
+            let _ = fs::remove_file(&paths.socket);
+            return Ok(Some(pid as u32));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    Err(Error::Custom(format!("Scan daemon {} did not exit within {}s", pid, STOP_TIMEOUT_SECS)))
+}
+
+/// Last lines of the daemon's stdout and stderr logs
+pub fn logs(paths: &DaemonPaths, lines: usize) -> Result<Vec<(PathBuf, Vec<String>)>, Error> {
+    let mut logs = vec![];
+    for path in vec![paths.stdout(), paths.stderr()] {
+        if !path.exists() {
+            continue;
+        }
+        let reader = BufReader::new(File::open(&path)?);
+        let all_lines = reader.lines().collect::<Result<Vec<String>, std::io::Error>>()?;
+        let skip = all_lines.len().saturating_sub(lines);
+        logs.push((path, all_lines.into_iter().skip(skip).collect()));
+    }
+
+    Ok(logs)
+}
@@ -2,17 +2,39 @@
 pub enum Error {
     InvalidPublicKey,
     InvalidSecretKey,
+    InvalidMetaAddress,
     Secp(secp256k1::Error),
     ParityCrypto(parity_crypto::publickey::Error),
     Io(std::io::Error),
     Daemonize(daemonize::DaemonizeError),
     Ethabi(ethabi::Error),
+    Web3(web3::Error),
+    Reqwest(reqwest::Error),
     HexEncDecError(hex::FromHexError),
     Aead(aead::Error),
     UintParsing(uint::FromDecStrErr),
+    Crypto(parity_crypto::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+    Query(QueryError),
+    InvalidPassphrase,
     Custom(String),
 }
 
+/// Failure to read announcements from the subgraph or the node
+#[derive(Debug)]
+pub enum QueryError {
+    /// The subgraph could not be reached
+    Subgraph(reqwest::Error),
+    /// The subgraph answered with GraphQL errors
+    GraphQL(Vec<graphql_client::Error>),
+    /// The subgraph answered without data
+    MissingData(&'static str),
+    /// The node failed a JSON-RPC request
+    Rpc(web3::Error),
+}
+
 impl From<secp256k1::Error> for Error {
     fn from(e: secp256k1::Error) -> Error {
         match e {
@@ -68,3 +90,51 @@ impl From<uint::FromDecStrErr> for Error {
         Error::UintParsing(e)
     }
 }
+
+impl From<parity_crypto::error::ScryptError> for Error {
+    fn from(e: parity_crypto::error::ScryptError) -> Error {
+        Error::Crypto(parity_crypto::Error::from(e))
+    }
+}
+
+impl From<parity_crypto::error::SymmError> for Error {
+    fn from(e: parity_crypto::error::SymmError) -> Error {
+        Error::Crypto(parity_crypto::Error::from(e))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::Json(e)
+    }
+}
+
+impl From<web3::Error> for Error {
+    fn from(e: web3::Error) -> Error {
+        Error::Web3(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Error {
+        Error::Csv(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Error {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::Reqwest(e)
+    }
+}
+
+impl From<QueryError> for Error {
+    fn from(e: QueryError) -> Error {
+        Error::Query(e)
+    }
+}
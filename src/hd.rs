@@ -0,0 +1,127 @@
+use bip39::{Language, Mnemonic, MnemonicType, Seed};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+use parity_crypto::publickey::{KeyPair, Secret};
+
+use crate::errors::Error;
+use crate::key;
+use crate::key::MasterKeys;
+
+// index offset of hardened children
+const HARDENED: u32 = 0x8000_0000;
+
+/// Default BIP-44 account path for Ethereum, the spend
+/// key is derived at `<path>/0/0` and the scan key at `<path>/1/0`
+pub const DEFAULT_ACCOUNT_PATH: &str = "m/44'/60'/0'";
+
+pub fn new_mnemonic() -> String {
+    let mnemonic = Mnemonic::new(MnemonicType::Words24, Language::English);
+    mnemonic.phrase().to_string()
+}
+
+pub fn master_keys_from_mnemonic(phrase: &str, account_path: &str) -> Result<MasterKeys, Error> {
+    // validate the phrase and compute the BIP-39 seed
+    let mnemonic = Mnemonic::from_phrase(phrase.trim(), Language::English)
+        .map_err(|e| Error::Custom(format!("Invalid mnemonic: {}", e)))?;
+    let seed = Seed::new(&mnemonic, "");
+
+    let master_keys = MasterKeys {
+        spend: derive(seed.as_bytes(), &format!("{}/0/0", account_path))?,
+        scan: derive(seed.as_bytes(), &format!("{}/1/0", account_path))?
+    };
+
+    Ok(master_keys)
+}
+
+pub fn derive(seed: &[u8], path: &str) -> Result<KeyPair, Error> {
+    // master secret and chain code from the seed
+    let (mut secret, mut chain_code) = hmac_sha512(b"Bitcoin seed", seed);
+    let mut keypair = KeyPair::from_secret(to_secret(&secret)?)?;
+
+    // derive the child key of every path component
+    for index in parse_path(path)? {
+        let mut data = Vec::with_capacity(37);
+        if index >= HARDENED {
+            data.push(0u8);
+            data.extend_from_slice(&secret);
+        } else {
+            let public_key = key::to_secp256k1_public(keypair.public())?;
+            data.extend_from_slice(&public_key.serialize());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+
+        let (tweak, child_chain_code) = hmac_sha512(&chain_code, &data);
+        let mut child_secret = to_secret(&tweak)?;
+        child_secret.add(keypair.secret())?;
+        keypair = KeyPair::from_secret(child_secret)?;
+
+        secret.copy_from_slice(keypair.secret().as_bytes());
+        chain_code = child_chain_code;
+    }
+
+    Ok(keypair)
+}
+
+fn parse_path(path: &str) -> Result<Vec<u32>, Error> {
+    let mut components = path.trim().split('/');
+    if components.next() != Some("m") {
+        return Err(Error::Custom(format!("Invalid derivation path: {}", path)));
+    }
+
+    components
+        .map(|component| {
+            let (number, offset) = match component.strip_suffix('\'') {
+                Some(number) => (number, HARDENED),
+                None => (component, 0)
+            };
+            match number.parse::<u32>() {
+                Ok(index) if index < HARDENED => Ok(index + offset),
+                _ => Err(Error::Custom(format!("Invalid derivation path: {}", path)))
+            }
+        })
+        .collect()
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut mac = Hmac::<Sha512>::new_varkey(key).expect("HMAC accepts keys of any length");
+    mac.input(data);
+    let result = mac.result().code();
+
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&result[0..32]);
+    right.copy_from_slice(&result[32..64]);
+    (left, right)
+}
+
+fn to_secret(bytes: &[u8; 32]) -> Result<Secret, Error> {
+    // keys outside the curve order are invalid,
+    // BIP-32 says to skip such (extremely unlikely) indices
+    let secret = Secret::from_slice(bytes).ok_or(Error::InvalidSecretKey)?;
+    KeyPair::from_secret(secret.clone())?;
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_bip32_test_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let keypair = derive(&seed, "m/0'/1/2'/2/1000000000").unwrap();
+        assert!(format!("{:x}", keypair.secret()) == "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8");
+    }
+
+    #[test]
+    fn test_master_keys_from_mnemonic() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let master_keys = master_keys_from_mnemonic(phrase, DEFAULT_ACCOUNT_PATH).unwrap();
+        assert!(format!("{:x}", master_keys.spend.address()) == "9858effd232b4033e47d90003d41ec34ecaeda94");
+        assert!(format!("{:x}", master_keys.scan.address()) == "399db6ed32539fbdf44c3e7678b5b428e378f666");
+        assert!(master_keys_from_mnemonic("abandon about", DEFAULT_ACCOUNT_PATH).is_err());
+        assert!(parse_path("44'/60'").is_err());
+    }
+}
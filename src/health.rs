@@ -0,0 +1,145 @@
+use std::cmp;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::errors::Error;
+
+// delay before the first retry, doubled
+// after every further failed attempt
+const BASE_BACKOFF_SECS: u64 = 10;
+const MAX_BACKOFF_SECS: u64 = 600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HealthState {
+    Healthy,
+    /// Failing, retried with exponential backoff
+    Degraded,
+    /// Error budget exhausted
+    Failed,
+}
+
+/// Health of the announcement source of a scanner
+#[derive(Debug)]
+pub struct Health {
+    pub state: HealthState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Consecutive failed attempts tolerated
+    error_budget: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Health {
+    pub fn new(error_budget: u32) -> Health {
+        Health {
+            state: HealthState::Healthy,
+            consecutive_failures: 0,
+            last_error: None,
+            error_budget: error_budget,
+            retry_at: None
+        }
+    }
+
+    pub fn set_error_budget(&mut self, error_budget: u32) {
+        self.error_budget = error_budget;
+    }
+
+    /// Whether the backoff after the last failure has elapsed
+    pub fn ready(&self) -> bool {
+        self.retry_at.map_or(true, |retry_at| Instant::now() >= retry_at)
+    }
+
+    pub fn backoff(&self) -> Duration {
+        let exponent = cmp::min(self.consecutive_failures.saturating_sub(1), 16);
+        Duration::from_secs(cmp::min(BASE_BACKOFF_SECS << exponent, MAX_BACKOFF_SECS))
+    }
+
+    /// Records a successful attempt, returns
+    /// whether the source recovered with it
+    pub fn record_success(&mut self) -> bool {
+        let recovered = self.state != HealthState::Healthy;
+        self.state = HealthState::Healthy;
+        self.consecutive_failures = 0;
+        self.last_error = None;
+        self.retry_at = None;
+
+        recovered
+    }
+
+    /// Records a failed attempt, fails once
+    /// the error budget is exhausted
+    pub fn record_failure(&mut self, error: &Error) -> Result<(), Error> {
+        self.consecutive_failures += 1;
+        self.last_error = Some(format!("{:?}", error));
+        if self.consecutive_failures > self.error_budget {
+            self.state = HealthState::Failed;
+            return Err(Error::Custom(format!(
+                "Error budget exhausted after {} failed attempts",
+                self.consecutive_failures
+            )));
+        }
+
+        self.state = HealthState::Degraded;
+        self.retry_at = Some(Instant::now() + self.backoff());
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for HealthState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HealthState::Healthy => write!(f, "healthy"),
+            HealthState::Degraded => write!(f, "degraded"),
+            HealthState::Failed => write!(f, "failed")
+        }
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.state, &self.last_error) {
+            (HealthState::Healthy, _) | (_, None) => write!(f, "{}", self.state),
+            (HealthState::Degraded, Some(last_error)) => write!(
+                f,
+                "{} ({} failed attempts, retrying in {}s): {}",
+                self.state,
+                self.consecutive_failures,
+                self.backoff().as_secs(),
+                last_error
+            ),
+            (HealthState::Failed, Some(last_error)) => write!(
+                f,
+                "{} ({} failed attempts): {}",
+                self.state,
+                self.consecutive_failures,
+                last_error
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_and_error_budget() {
+        let mut health = Health::new(2);
+        let error = Error::Custom(String::from("indexer down"));
+
+        assert!(health.record_failure(&error).is_ok());
+        assert!(health.state == HealthState::Degraded);
+        assert!(health.backoff() == Duration::from_secs(10));
+        assert!(!health.ready());
+
+        assert!(health.record_failure(&error).is_ok());
+        assert!(health.backoff() == Duration::from_secs(20));
+
+        assert!(health.record_failure(&error).is_err());
+        assert!(health.state == HealthState::Failed);
+
+        assert!(health.record_success());
+        assert!(health.state == HealthState::Healthy && health.ready());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Error;
+use crate::scheme::StealthScheme;
+use crate::utils::unix_timestamp;
+
+// not a `.json` file, so that it is never
+// mistaken for a key file in the storage dir
+static INDEX_FILENAME: &'static str = "wallet.index";
+
+/// Provenance of every stealth key derived in a storage dir
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct WalletIndex {
+    pub keys: Vec<IndexEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexEntry {
+    pub address: String,
+    pub master_address: String,
+    pub nonce_point: String,
+    #[serde(default)]
+    pub scheme: StealthScheme,
+    pub announcement: Option<Announcement>,
+    /// Announcement dropped by a chain reorganisation
+    #[serde(default)]
+    pub orphaned: bool,
+    /// Unix timestamp of the derivation
+    pub derived_at: u64,
+}
+
+/// On-chain announcement of a stealth transfer
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Announcement {
+    pub tx_hash: String,
+    pub block: u64,
+}
+
+impl WalletIndex {
+    pub fn get(&self, address: &str) -> Option<&IndexEntry> {
+        let address = address.to_lowercase();
+        self.keys.iter().find(|entry| entry.address == address)
+    }
+}
+
+pub fn load(storage_dir: &PathBuf) -> Result<WalletIndex, Error> {
+    let mut index_path = storage_dir.clone();
+    index_path.push(INDEX_FILENAME);
+
+    // a storage dir without derived keys has no index yet
+    if !index_path.exists() {
+        return Ok(WalletIndex::default());
+    }
+    let file = File::open(index_path)?;
+    let wallet_index: WalletIndex = serde_json::from_reader(file)?;
+
+    Ok(wallet_index)
+}
+
+pub fn record(
+    storage_dir: &PathBuf,
+    address: &str,
+    master_address: &str,
+    nonce_point: &str,
+    scheme: StealthScheme,
+    announcement: Option<&Announcement>
+) -> Result<IndexEntry, Error> {
+    let mut wallet_index = load(&storage_dir)?;
+    let address = address.to_lowercase();
+
+    // deriving the same key again keeps its first record,
+    // only filling in a missing or re-included announcement
+    let entry = match wallet_index.keys.iter_mut().find(|entry| entry.address == address) {
+        Some(entry) => {
+            if announcement.is_some() && (entry.announcement.is_none() || entry.orphaned) {
+                entry.announcement = announcement.cloned();
+                entry.orphaned = false;
+            }
+            entry.clone()
+        },
+        None => {
+            let derived_at = unix_timestamp();
+            let entry = IndexEntry {
+                address: address,
+                master_address: master_address.to_lowercase(),
+                nonce_point: String::from(nonce_point),
+                scheme: scheme,
+                announcement: announcement.cloned(),
+                orphaned: false,
+                derived_at: derived_at
+            };
+            wallet_index.keys.push(entry.clone());
+            entry
+        }
+    };
+
+    store(&storage_dir, &wallet_index)?;
+
+    Ok(entry)
+}
+
+/// Marks the keys of a master key announced
+/// after the given block as orphaned
+pub fn mark_orphaned(
+    storage_dir: &PathBuf,
+    master_address: &str,
+    block: u64
+) -> Result<Vec<IndexEntry>, Error> {
+    let mut wallet_index = load(&storage_dir)?;
+    let master_address = master_address.to_lowercase();
+
+    let mut orphaned = vec![];
+    for entry in wallet_index.keys.iter_mut() {
+        let announced_after = entry.announcement.as_ref().map_or(false, |announcement| announcement.block > block);
+        if entry.master_address == master_address && announced_after && !entry.orphaned {
+            entry.orphaned = true;
+            orphaned.push(entry.clone());
+        }
+    }
+    if !orphaned.is_empty() {
+        store(&storage_dir, &wallet_index)?;
+    }
+
+    Ok(orphaned)
+}
+
+fn store(storage_dir: &PathBuf, wallet_index: &WalletIndex) -> Result<(), Error> {
+    let mut index_path = storage_dir.clone();
+    index_path.push(INDEX_FILENAME);
+
+    // write to a temporary file first, so that a
+    // crash never leaves a truncated index behind
+    let tmp_path = index_path.with_extension("index.tmp");
+    let mut file = File::create(&tmp_path)?;
+    write!(file, "{}", serde_json::to_string_pretty(&wallet_index)?)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &index_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let announcement = Announcement { tx_hash: String::from("0xabcd"), block: 42 };
+        record(&storage_dir, "0xAA", "0xbb", "02cc", StealthScheme::RsTx, None).unwrap();
+        record(&storage_dir, "0xaa", "0xbb", "02cc", StealthScheme::RsTx, Some(&announcement)).unwrap();
+        record(&storage_dir, "0xdd", "0xbb", "03ee", StealthScheme::Eip5564, Some(&announcement)).unwrap();
+
+        let wallet_index = load(&storage_dir).unwrap();
+        assert!(wallet_index.keys.len() == 2);
+        assert!(wallet_index.get("0xAA").unwrap().announcement.as_ref().unwrap().block == 42);
+
+        // a reorg orphans the keys announced after
+        // the fork, until they are re-included
+        assert!(mark_orphaned(&storage_dir, "0xBB", 41).unwrap().len() == 2);
+        let reincluded = Announcement { tx_hash: String::from("0xef01"), block: 43 };
+        record(&storage_dir, "0xaa", "0xbb", "02cc", StealthScheme::RsTx, Some(&reincluded)).unwrap();
+        let wallet_index = load(&storage_dir).unwrap();
+        assert!(!wallet_index.get("0xaa").unwrap().orphaned);
+        assert!(wallet_index.get("0xaa").unwrap().announcement.as_ref().unwrap().block == 43);
+        assert!(wallet_index.get("0xdd").unwrap().orphaned);
+    }
+}
@@ -1,65 +1,419 @@
-use std::fs::File;
-use std::io::{BufReader, Write};
-use std::io::BufRead;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use parity_crypto::{aes, derive_key_iterations, derive_mac, is_equal, scrypt};
+use parity_crypto::Keccak256;
 use parity_crypto::publickey::{Address, Generator, KeyPair, Public, Random, Secret};
+use parity_crypto::publickey::ec_math_utils;
 use parity_crypto::publickey::public_to_address;
 
+use rand::RngCore;
+use rand::rngs::OsRng;
+
 use secp256k1::PublicKey;
 
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
 use crate::errors::Error;
+use crate::hd;
+
+// scrypt parameters of geth's
+// standard (non-light) keystore
+#[cfg(not(test))]
+const SCRYPT_N: u32 = 262144;
+// light enough for the tests to round trip quickly
+#[cfg(test)]
+const SCRYPT_N: u32 = 4096;
+const SCRYPT_P: u32 = 1;
+const SCRYPT_R: u32 = 8;
+const DKLEN: u32 = 32;
+
+/// Web3 Secret Storage (v3) key file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyFile {
+    pub address: String,
+    #[serde(alias = "Crypto")]
+    pub crypto: Crypto,
+    pub id: String,
+    pub version: u8,
+    /// Compressed public key, readable without
+    /// the passphrase (ignored by other wallets)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// Origin of keys derived by `receive`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stealth: Option<StealthOrigin>,
+}
+
+/// Master key and nonce point
+/// a stealth key was derived from
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StealthOrigin {
+    pub master_address: String,
+    pub nonce_point: String,
+}
+
+/// Plaintext part of a key file,
+/// readable without the passphrase
+#[derive(Debug)]
+pub struct KeyInfo {
+    pub address: String,
+    pub public_key: Option<String>,
+    pub legacy: bool,
+    pub stealth: Option<StealthOrigin>,
+}
+
+/// Spend and scan (view) keypairs of a master key.
+/// The master is identified by the spend key address,
+/// the scan key is stored alongside as `<address>.scan.json`
+pub struct MasterKeys {
+    pub spend: KeyPair,
+    pub scan: KeyPair,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Crypto {
+    pub cipher: String,
+    pub cipherparams: CipherParams,
+    pub ciphertext: String,
+    #[serde(flatten)]
+    pub kdf: Kdf,
+    pub mac: String,
+}
 
-pub fn new(path: &PathBuf) -> Result<(), Error> {
-    // generate random keypair
-    let keypair = Random.generate();
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kdf", content = "kdfparams", rename_all = "lowercase")]
+pub enum Kdf {
+    Scrypt(ScryptParams),
+    Pbkdf2(Pbkdf2Params),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScryptParams {
+    pub dklen: u32,
+    pub n: u32,
+    pub p: u32,
+    pub r: u32,
+    pub salt: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Pbkdf2Params {
+    pub c: u32,
+    pub dklen: u32,
+    pub prf: String,
+    pub salt: String,
+}
+
+pub fn new(path: &PathBuf, passphrase: &str) -> Result<MasterKeys, Error> {
+    // generate random spend and scan keypairs
+    let master_keys = MasterKeys {
+        spend: Random.generate(),
+        scan: Random.generate()
+    };
+
+    // store keypairs
+    store_master(&path, &master_keys, &passphrase)?;
+
+    Ok(master_keys)
+}
+
+pub fn new_from_mnemonic(
+    path: &PathBuf,
+    passphrase: &str,
+    phrase: &str,
+    account_path: &str,
+    force: bool
+) -> Result<MasterKeys, Error> {
+    // derive spend and scan keypairs from the mnemonic
+    let master_keys = hd::master_keys_from_mnemonic(&phrase, &account_path)?;
+
+    // never replace an existing key file unless asked to
+    let filename = format!("{:?}.json", master_keys.spend.address());
+    if !force && path.join(&filename).exists() {
+        return Err(Error::Custom(format!("Key file {} already exists, use --force to overwrite it", filename)));
+    }
 
-    // store keypair
-    store(&path, &keypair)?;
+    // store keypairs
+    store_master(&path, &master_keys, &passphrase)?;
+
+    Ok(master_keys)
+}
+
+pub fn store_master(path: &PathBuf, master_keys: &MasterKeys, passphrase: &str) -> Result<(), Error> {
+    store(&path, &master_keys.spend, &passphrase)?;
+    let master_address = format!("{:?}", master_keys.spend.address());
+    store_scan(&path, &master_address, &master_keys.scan, &passphrase)?;
 
     Ok(())
 }
 
-pub fn store(path: &PathBuf, keypair: &KeyPair) -> Result<(), Error> {
-    // create file to write keypair data
-    let mut copy_path = path.clone();
+pub fn store(path: &PathBuf, keypair: &KeyPair, passphrase: &str) -> Result<(), Error> {
+    let filename = format!("{:?}.json", keypair.address());
+    write_key_file(&path, &filename, &keypair, &passphrase, None)
+}
+
+pub fn store_scan(path: &PathBuf, master_address: &str, keypair: &KeyPair, passphrase: &str) -> Result<(), Error> {
+    let filename = format!("{}.scan.json", master_address);
+    write_key_file(&path, &filename, &keypair, &passphrase, None)
+}
+
+pub fn store_stealth(
+    path: &PathBuf,
+    keypair: &KeyPair,
+    passphrase: &str,
+    origin: &StealthOrigin
+) -> Result<(), Error> {
     let filename = format!("{:?}.json", keypair.address());
+    write_key_file(&path, &filename, &keypair, &passphrase, Some(origin))
+}
+
+fn write_key_file(
+    path: &PathBuf,
+    filename: &str,
+    keypair: &KeyPair,
+    passphrase: &str,
+    origin: Option<&StealthOrigin>
+) -> Result<(), Error> {
+    // encrypt the secret key as a web3 secret storage
+    // file, before anything is written to the disk
+    let mut key_file = encrypt(&keypair, &passphrase)?;
+    key_file.stealth = origin.cloned();
+    let content = serde_json::to_string_pretty(&key_file)?;
+
+    let mut copy_path = path.clone();
     copy_path.push(filename);
-    let mut file = File::create(copy_path)?;
-    let public_key = keypair.public();
-    let secp_public_key = to_secp256k1_public(&public_key).unwrap();
-
-    // content of the key file
-    let content = format!(
-        "Secret Key: {:x}\nPublic Key (Uncompressed): {:x}\nPublic Key (Compressed): {}\nAddress: {:?}",
-        keypair.secret(), keypair.public(), secp_public_key.to_string(), keypair.address()
-    );
+    write_private(&copy_path, &content)
+}
+
+/// Writes a file readable by its owner only, through a temporary
+/// file so that a crash never leaves a truncated key file behind
+pub fn write_private(path: &PathBuf, content: &str) -> Result<(), Error> {
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
     write!(file, "{}", content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, &path)?;
 
     Ok(())
 }
 
-pub fn load(path: &PathBuf, address: &str) -> Result<KeyPair, Error> {
+pub fn load(path: &PathBuf, address: &str, passphrase: &str) -> Result<KeyPair, Error> {
+    let filename = format!("{}.json", address);
+    read_key_file(&path, &filename, &passphrase)
+}
+
+pub fn load_scan(path: &PathBuf, master_address: &str, passphrase: &str) -> Result<KeyPair, Error> {
+    // master keys created before the dual-key scheme
+    // have no scan key, their spend key doubles as one
+    let filename = format!("{}.scan.json", master_address);
+    let mut copy_path = path.clone();
+    copy_path.push(&filename);
+    if copy_path.exists() {
+        read_key_file(&path, &filename, &passphrase)
+    } else {
+        load(&path, &master_address, &passphrase)
+    }
+}
+
+pub fn load_public(path: &PathBuf, address: &str) -> Result<Public, Error> {
+    // get filepath of stored keypair
+    let mut copy_path = path.clone();
+    copy_path.push(format!("{}.json", address));
+
+    // the public key is stored in plaintext
+    // so this does not require the passphrase
+    let public_key_str = inspect(&copy_path)?
+        .public_key
+        .ok_or_else(|| Error::Custom(String::from("Key file has no public key")))?;
+    let (public_key, _) = public_key_from_str(&public_key_str)?;
+
+    Ok(public_key)
+}
+
+pub fn inspect(file_path: &PathBuf) -> Result<KeyInfo, Error> {
+    let content = fs::read_to_string(&file_path)?;
+    let key_info = if is_legacy(&content) {
+        KeyInfo {
+            address: legacy_field(&content, "Address: ")?.trim_start_matches("0x").to_lowercase(),
+            public_key: Some(legacy_field(&content, "Public Key (Compressed): ")?.to_string()),
+            legacy: true,
+            stealth: None
+        }
+    } else {
+        let key_file: KeyFile = serde_json::from_str(&content)?;
+        KeyInfo {
+            address: key_file.address.trim_start_matches("0x").to_lowercase(),
+            public_key: key_file.public_key,
+            legacy: false,
+            stealth: key_file.stealth
+        }
+    };
+
+    Ok(key_info)
+}
+
+fn read_key_file(path: &PathBuf, filename: &str, passphrase: &str) -> Result<KeyPair, Error> {
     // get filepath of stored keypair
     let mut copy_path = path.clone();
-    let filename = format!("{}.json", address);
     copy_path.push(filename);
 
-    // open file and read the first line (secret key)
-    let file = File::open(copy_path)?;
-    let mut buffer = BufReader::new(file);
-    let mut secret_key_line = String::new();
-    buffer.read_line(&mut secret_key_line).expect("Unable to read line");
-    let secret_key_str = str::replace(&secret_key_line, "Secret Key: ", "");
+    // read the key file, legacy plaintext files
+    // are still accepted until they are migrated
+    let content = fs::read_to_string(&copy_path)?;
+    let keypair = if is_legacy(&content) {
+        eprintln!("[key] {:?} is a legacy plaintext key file, run `migrate`", copy_path);
+        parse_legacy(&content)?
+    } else {
+        let key_file: KeyFile = serde_json::from_str(&content)?;
+        decrypt(&key_file, &passphrase)?
+    };
 
-    // form keyapair
-    let secret_key = Secret::from_str(&secret_key_str).unwrap();
-    let keypair = KeyPair::from_secret(secret_key).unwrap();
+    Ok(keypair)
+}
+
+pub fn is_legacy(content: &str) -> bool {
+    content.starts_with("Secret Key: ")
+}
+
+fn legacy_field<'a>(content: &'a str, label: &str) -> Result<&'a str, Error> {
+    // legacy key files consist of "Label: value" lines
+    content
+        .lines()
+        .find(|line| line.starts_with(label))
+        .map(|line| line[label.len()..].trim())
+        .ok_or_else(|| Error::Custom(format!("Legacy key file is missing \"{}\"", label)))
+}
+
+pub fn parse_legacy(content: &str) -> Result<KeyPair, Error> {
+    let secret_key = Secret::from_str(legacy_field(&content, "Secret Key: ")?)?;
+    let keypair = KeyPair::from_secret(secret_key)?;
+
+    // the stored address must match the secret key
+    if format!("{:?}", keypair.address()) != legacy_field(&content, "Address: ")?.to_lowercase() {
+        return Err(Error::Custom(String::from("Key file address does not match its secret key")));
+    }
 
     Ok(keypair)
 }
 
+pub fn encrypt(keypair: &KeyPair, passphrase: &str) -> Result<KeyFile, Error> {
+    let mut salt = [0u8; 32];
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut iv);
+
+    // derive the encryption and mac keys
+    let (encryption_key, mac_key) = scrypt::derive_key(
+        passphrase.as_bytes(), &salt, SCRYPT_N, SCRYPT_P, SCRYPT_R
+    )?;
+
+    // encrypt secret key and compute mac
+    let secret = keypair.secret().as_bytes();
+    let mut ciphertext = vec![0u8; secret.len()];
+    aes::encrypt_128_ctr(&encryption_key, &iv, secret, &mut ciphertext)?;
+    let mac = derive_mac(&mac_key, &ciphertext).keccak256();
+
+    let key_file = KeyFile {
+        address: format!("{:x}", keypair.address()),
+        crypto: Crypto {
+            cipher: String::from("aes-128-ctr"),
+            cipherparams: CipherParams { iv: hex::encode(iv) },
+            ciphertext: hex::encode(ciphertext),
+            kdf: Kdf::Scrypt(ScryptParams {
+                dklen: DKLEN,
+                n: SCRYPT_N,
+                p: SCRYPT_P,
+                r: SCRYPT_R,
+                salt: hex::encode(salt)
+            }),
+            mac: hex::encode(mac)
+        },
+        id: Uuid::new_v4().to_string(),
+        version: 3,
+        public_key: Some(to_secp256k1_public(keypair.public())?.to_string()),
+        stealth: None
+    };
+
+    Ok(key_file)
+}
+
+pub fn decrypt(key_file: &KeyFile, passphrase: &str) -> Result<KeyPair, Error> {
+    let crypto = &key_file.crypto;
+    if key_file.version != 3 || crypto.cipher != "aes-128-ctr" {
+        return Err(Error::Custom(String::from("Unsupported key file version or cipher")));
+    }
+
+    // derive the encryption and mac keys
+    let (encryption_key, mac_key) = match &crypto.kdf {
+        Kdf::Scrypt(params) if params.dklen == DKLEN => {
+            let salt = hex::decode(&params.salt)?;
+            scrypt::derive_key(passphrase.as_bytes(), &salt, params.n, params.p, params.r)?
+        },
+        Kdf::Pbkdf2(params) if params.dklen == DKLEN && params.prf == "hmac-sha256" => {
+            let salt = hex::decode(&params.salt)?;
+            derive_key_iterations(passphrase.as_bytes(), &salt, params.c)
+        },
+        _ => return Err(Error::Custom(String::from("Unsupported key derivation parameters")))
+    };
+
+    // verify the mac before decrypting
+    let ciphertext = hex::decode(&crypto.ciphertext)?;
+    let mac = derive_mac(&mac_key, &ciphertext).keccak256();
+    if !is_equal(&mac, &hex::decode(&crypto.mac)?) {
+        return Err(Error::InvalidPassphrase);
+    }
+
+    // decrypt the secret key and form keypair
+    let iv = hex::decode(&crypto.cipherparams.iv)?;
+    let mut secret = vec![0u8; ciphertext.len()];
+    aes::decrypt_128_ctr(&encryption_key, &iv, &ciphertext, &mut secret)?;
+    let secret_key = Secret::from_slice(&secret).ok_or(Error::InvalidSecretKey)?;
+    let keypair = KeyPair::from_secret(secret_key)?;
+
+    // the stored address must match the decrypted key
+    if format!("{:x}", keypair.address()) != key_file.address.trim_start_matches("0x").to_lowercase() {
+        return Err(Error::Custom(String::from("Key file address does not match its secret key")));
+    }
+
+    Ok(keypair)
+}
+
+pub fn ecdh_shared_secret(public: &Public, secret: &Secret) -> Result<[u8; 32], Error> {
+    // hash of the ECDH point, used both as the
+    // encryption key and as the stealth scalar
+    let mut ecdh_shared_secret = public.clone();
+    ec_math_utils::public_mul_secret(&mut ecdh_shared_secret, &secret)?;
+
+    Ok(ecdh_shared_secret.keccak256())
+}
+
+pub fn stealth_public(spend_public: &Public, shared_secret: &[u8; 32]) -> Result<Public, Error> {
+    // spend public key + shared_secret * G
+    let secret_number = Secret::from_str(&hex::encode(shared_secret))?;
+    let mut stealth_public = ec_math_utils::generation_point();
+    ec_math_utils::public_mul_secret(&mut stealth_public, &secret_number)?;
+    ec_math_utils::public_add(&mut stealth_public, &spend_public)?;
+
+    Ok(stealth_public)
+}
+
 pub fn public_key_from_str(pk: &str) -> Result<(Public, Address), Error> {
     let secp_public_key = PublicKey::from_str(pk)?;
     let mut public_key = Public::default();
@@ -92,6 +446,8 @@ pub fn set_public(public: &mut Public, key_public: &PublicKey) {
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
     use super::*;
 
     #[test]
@@ -101,4 +457,85 @@ mod tests {
         assert!(format!("{:x}", pk_valid) == "9a86a8531249e6bf23b0b1792e5983d5eabf3389977423fe4c9d82fb27b347e9fd2167dded063e20e461e7cf417f39174da9212a6c3f533e04b5df59075c3866");
         assert!(pk_invalid == Err(secp256k1::Error::InvalidPublicKey));
     }
+
+    #[test]
+    fn test_decrypt_pbkdf2_test_vector() {
+        let key_file: KeyFile = serde_json::from_str(r#"{
+            "crypto": {
+                "cipher": "aes-128-ctr",
+                "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+                "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+                "kdf": "pbkdf2",
+                "kdfparams": {
+                    "c": 262144,
+                    "dklen": 32,
+                    "prf": "hmac-sha256",
+                    "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+                },
+                "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+            },
+            "address": "008aeeda4d805471df9b2a5b0f38a0c3bcba786b",
+            "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+            "version": 3
+        }"#).unwrap();
+
+        let keypair = decrypt(&key_file, "testpassword").unwrap();
+        assert!(format!("{:x}", keypair.secret()) == "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d");
+        assert!(decrypt(&key_file, "wrongpassword").is_err());
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        let keypair = Random.generate();
+        let content = format!(
+            "Secret Key: {:x}\nPublic Key (Uncompressed): {:x}\nPublic Key (Compressed): {}\nAddress: {:?}",
+            keypair.secret(), keypair.public(), to_secp256k1_public(keypair.public()).unwrap(), keypair.address()
+        );
+        assert!(is_legacy(&content));
+        assert!(parse_legacy(&content).unwrap().secret() == keypair.secret());
+
+        let tampered = content.replace("Address: 0x", "Address: 0xff");
+        assert!(parse_legacy(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_dual_key_stealth_derivation() {
+        let spend = Random.generate();
+        let scan = Random.generate();
+        let nonce = Random.generate();
+
+        // sender uses the scan public key, recipient the nonce point
+        let sender_secret = ecdh_shared_secret(scan.public(), nonce.secret()).unwrap();
+        let recipient_secret = ecdh_shared_secret(nonce.public(), scan.secret()).unwrap();
+        assert!(sender_secret == recipient_secret);
+
+        // only the spend secret yields the stealth secret key
+        let stealth = stealth_public(spend.public(), &sender_secret).unwrap();
+        let mut stealth_secret = spend.secret().clone();
+        stealth_secret.add(&Secret::from_str(&hex::encode(recipient_secret)).unwrap()).unwrap();
+        assert!(KeyPair::from_secret(stealth_secret).unwrap().public() == &stealth);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let keypair = Random.generate();
+        let key_file = encrypt(&keypair, "passphrase").unwrap();
+        let decrypted_keypair = decrypt(&key_file, "passphrase").unwrap();
+        assert!(decrypted_keypair.secret() == keypair.secret());
+    }
+
+    #[test]
+    fn test_store_private() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+        let keypair = Random.generate();
+        let address = format!("{:?}", keypair.address());
+        store_scan(&storage_dir, &address, &keypair, "passphrase").unwrap();
+
+        let mut path = storage_dir.clone();
+        path.push(format!("{}.scan.json", address));
+        assert!(fs::metadata(&path).unwrap().permissions().mode() & 0o777 == 0o600);
+        assert!(!path.with_extension("json.tmp").exists());
+        assert!(load_scan(&storage_dir, &address, "passphrase").unwrap().secret() == keypair.secret());
+    }
 }
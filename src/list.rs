@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use web3::futures::Future;
+use web3::types::H160;
+
+use crate::errors::Error;
+use crate::index;
+use crate::index::Announcement;
+use crate::key;
+use crate::meta_address::{StealthMetaAddress, DEFAULT_CHAIN};
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyKind {
+    Master,
+    Scan,
+    Stealth,
+    /// Legacy key that may be a master or a stealth key
+    Unknown,
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyEntry {
+    pub kind: KeyKind,
+    pub address: String,
+    pub public_key: Option<String>,
+    /// Stealth meta-address of a master key
+    pub meta_address: Option<String>,
+    pub legacy: bool,
+    /// Master key of a scan or stealth key
+    pub master_address: Option<String>,
+    /// Nonce point a stealth key was derived from
+    pub nonce_point: Option<String>,
+    /// Announcement a stealth key was derived from
+    pub announcement: Option<Announcement>,
+    /// Whether that announcement was orphaned by a reorg
+    pub orphaned: bool,
+    /// Balance in wei, if requested
+    pub balance: Option<String>,
+}
+
+pub fn list(storage_dir: &PathBuf, with_balances: bool) -> Result<Vec<KeyEntry>, Error> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(storage_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+    let wallet_index = index::load(&storage_dir)?;
+    let scan_masters: Vec<String> = paths
+        .iter()
+        .filter_map(|path| path.file_name()?.to_str()?.strip_suffix(".scan.json").map(str::to_lowercase))
+        .collect();
+
+    // classify every key file in the storage dir
+    let mut entries = vec![];
+    for path in paths {
+        let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+        let key_info = match key::inspect(&path) {
+            Ok(key_info) => key_info,
+            Err(e) => {
+                eprintln!("[list] Skipping {}: {:?}", filename, e);
+                continue;
+            }
+        };
+
+        // the wallet index also knows the origin of stealth
+        // keys whose files were migrated from the legacy format
+        let address = format!("0x{}", key_info.address);
+        let index_entry = wallet_index.get(&address);
+        let origin = key_info.stealth.or_else(|| {
+            index_entry.map(|entry| key::StealthOrigin {
+                master_address: entry.master_address.clone(),
+                nonce_point: entry.nonce_point.clone()
+            })
+        });
+        let announcement = index_entry.and_then(|entry| entry.announcement.clone());
+        let orphaned = index_entry.map_or(false, |entry| entry.orphaned);
+
+        let entry = match (filename.strip_suffix(".scan.json"), origin) {
+            (Some(master_address), _) => KeyEntry {
+                kind: KeyKind::Scan,
+                address: address,
+                public_key: key_info.public_key,
+                meta_address: None,
+                legacy: key_info.legacy,
+                master_address: Some(String::from(master_address)),
+                nonce_point: None,
+                announcement: None,
+                orphaned: false,
+                balance: None
+            },
+            (None, Some(origin)) => KeyEntry {
+                kind: KeyKind::Stealth,
+                address: address,
+                public_key: key_info.public_key,
+                meta_address: None,
+                legacy: key_info.legacy,
+                master_address: Some(origin.master_address),
+                nonce_point: Some(origin.nonce_point),
+                announcement: announcement,
+                orphaned: orphaned,
+                balance: None
+            },
+            // legacy files do not tell stealth keys from master keys,
+            // only a scan key or a wallet index entry does
+            (None, None) => KeyEntry {
+                kind: if key_info.legacy && !scan_masters.contains(&address.to_lowercase()) {
+                    KeyKind::Unknown
+                } else {
+                    KeyKind::Master
+                },
+                address: address,
+                public_key: key_info.public_key,
+                meta_address: None,
+                legacy: key_info.legacy,
+                master_address: None,
+                nonce_point: None,
+                announcement: None,
+                orphaned: false,
+                balance: None
+            }
+        };
+        entries.push(entry);
+    }
+
+    // master keys without a separate scan
+    // key use their own key for scanning
+    let meta_addresses: Vec<Option<String>> = entries
+        .iter()
+        .map(|entry| {
+            if entry.kind != KeyKind::Master {
+                return None;
+            }
+            let scan_public_key = entries
+                .iter()
+                .find(|scan| scan.kind == KeyKind::Scan && scan.master_address.as_ref() == Some(&entry.address))
+                .unwrap_or(entry)
+                .public_key
+                .as_ref()?;
+            let (spend_public, _) = key::public_key_from_str(entry.public_key.as_ref()?).ok()?;
+            let (scan_public, _) = key::public_key_from_str(scan_public_key).ok()?;
+            Some(StealthMetaAddress::new(DEFAULT_CHAIN, &spend_public, &scan_public).to_string())
+        })
+        .collect();
+    for (entry, meta_address) in entries.iter_mut().zip(meta_addresses) {
+        entry.meta_address = meta_address;
+    }
+
+    if with_balances {
+        // instantiate web3
+        let (_eloop, transport) = web3::transports::Http::new("http://127.0.0.1:8545").unwrap();
+        let web3 = web3::Web3::new(transport);
+
+        // scan keys never hold funds
+        for entry in entries.iter_mut().filter(|entry| entry.kind != KeyKind::Scan) {
+            let address = H160::from_slice(&hex::decode(&entry.address[2..])?);
+            match web3.eth().balance(address, None).wait() {
+                Ok(balance) => entry.balance = Some(balance.to_string()),
+                Err(e) => eprintln!("[list] Error fetching balance of {}: {:?}", entry.address, e)
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Addresses of all master keys in the storage dir
+pub fn master_addresses(storage_dir: &PathBuf) -> Result<Vec<String>, Error> {
+    let master_addresses = list(&storage_dir, false)?
+        .into_iter()
+        .filter(|entry| entry.kind == KeyKind::Master)
+        .map(|entry| entry.address)
+        .collect();
+
+    Ok(master_addresses)
+}
+
+#[cfg(test)]
+mod tests {
+    use parity_crypto::publickey::{Generator, Random};
+
+    use super::*;
+
+    #[test]
+    fn test_master_addresses() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let master_keys = key::MasterKeys { spend: Random.generate(), scan: Random.generate() };
+        key::store_master(&storage_dir, &master_keys, "passphrase").unwrap();
+        let master_address = format!("{:?}", master_keys.spend.address());
+
+        // a legacy key file the wallet index does not know
+        let legacy = Random.generate();
+        let mut legacy_path = storage_dir.clone();
+        legacy_path.push(format!("{:?}.json", legacy.address()));
+        fs::write(&legacy_path, format!(
+            "Secret Key: {:x}\nPublic Key (Uncompressed): {:x}\nPublic Key (Compressed): {}\nAddress: {:?}",
+            legacy.secret(), legacy.public(), key::to_secp256k1_public(legacy.public()).unwrap(), legacy.address()
+        )).unwrap();
+
+        let entries = list(&storage_dir, false).unwrap();
+        let legacy_entry = entries.iter().find(|entry| entry.legacy).unwrap();
+        assert!(legacy_entry.kind == KeyKind::Unknown);
+        assert!(master_addresses(&storage_dir).unwrap() == vec![master_address]);
+    }
+}
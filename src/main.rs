@@ -2,21 +2,53 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 
 extern crate aead;
+extern crate bip39;
+extern crate chrono;
+extern crate csv;
 extern crate daemonize;
 extern crate ethabi;
 extern crate graphql_client;
 extern crate hex;
+extern crate hmac;
 extern crate job_scheduler;
+extern crate libc;
 extern crate parity_crypto;
+extern crate rand;
+extern crate rayon;
+extern crate rpassword;
+extern crate rusqlite;
 extern crate secp256k1;
+extern crate serde_json;
+extern crate sha2;
+extern crate signal_hook;
+#[cfg(test)]
+extern crate tempfile;
 extern crate uint;
+extern crate uuid;
 extern crate web3;
 
+mod backfill;
+mod checkpoint;
+mod client;
+mod config;
+mod daemon;
 mod errors;
+mod hd;
+mod health;
+mod index;
 mod key;
+mod list;
+mod meta_address;
+mod migrate;
+mod notify;
+mod payload;
+mod payments;
 mod query;
 mod receive;
 mod scan;
+mod scheme;
+mod source;
+mod sweep;
 mod transfer;
 mod utils;
 
@@ -24,13 +56,51 @@ mod utils;
 #[derive(StructOpt, Debug)]
 #[structopt(name = "RsTx Client")]
 enum Cli {
-    /// Create a new keypair
+    /// Create a new master key
+    /// (spend and scan keypairs)
     #[structopt(name = "create")]
     Create {
         /// Directory to store
         /// the created keypair
         #[structopt(short = "s", parse(from_os_str))]
-        storage_dir: PathBuf
+        storage_dir: PathBuf,
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+        /// Derive the keypairs from a new
+        /// BIP-39 mnemonic (printed once)
+        #[structopt(short = "m")]
+        mnemonic: bool,
+        /// BIP-32 account path, used
+        /// along with the mnemonic
+        #[structopt(long = "path", raw(default_value = "hd::DEFAULT_ACCOUNT_PATH"))]
+        account_path: String
+    },
+    /// Restore a master key
+    /// from its BIP-39 mnemonic
+    #[structopt(name = "restore")]
+    Restore {
+        /// Directory to store
+        /// the restored keypair
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+        /// File containing the
+        /// mnemonic (prompted if absent)
+        #[structopt(short = "m", parse(from_os_str))]
+        mnemonic_file: Option<PathBuf>,
+        /// BIP-32 account path the
+        /// master key was created with
+        #[structopt(long = "path", raw(default_value = "hd::DEFAULT_ACCOUNT_PATH"))]
+        account_path: String,
+        /// Overwrite the key files
+        /// if they already exist
+        #[structopt(long = "force")]
+        force: bool
     },
     /// List all ECDH keypairs
     #[structopt(name = "list")]
@@ -38,7 +108,14 @@ enum Cli {
         /// Directory from which
         /// to list keypairs
         #[structopt(short = "s", parse(from_os_str))]
-        storage_dir: PathBuf
+        storage_dir: PathBuf,
+        /// Query the balance
+        /// of every key
+        #[structopt(short = "b")]
+        balances: bool,
+        /// Print the keys as JSON
+        #[structopt(long = "json")]
+        json: bool
     },
     /// Transfer ether
     #[structopt(name = "transfer")]
@@ -51,10 +128,24 @@ enum Cli {
         /// from the key in storage_dir
         #[structopt(short = "f")]
         from: String,
-        /// Recipient public key
-        /// in compressed form
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+        /// Recipient stealth meta-address
+        /// (st:<chain>:0x<spend><view>)
+        /// or master public key
         #[structopt(short = "t")]
         to: String,
+        /// Chain short name the
+        /// meta-address must be for
+        #[structopt(short = "c", default_value = "eth")]
+        chain: String,
+        /// Stealth scheme (rstx or eip5564),
+        /// eip5564 for st: meta-addresses
+        /// and rstx for public keys by default
+        #[structopt(long = "scheme", parse(try_from_str = "parse_scheme"))]
+        scheme: Option<scheme::StealthScheme>,
         /// Value to be transferred
         /// (in wei)
         #[structopt(short = "v")]
@@ -70,15 +161,51 @@ enum Cli {
         /// Master key address
         #[structopt(short = "a")]
         address: String,
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
         /// Nonce point (in compressed form)
         /// of the stealth transaction
         #[structopt(short = "n")]
-        nonce_point: String
+        nonce_point: String,
+        /// Stealth scheme of the
+        /// transfer (rstx or eip5564)
+        #[structopt(long = "scheme", default_value = "rstx", parse(try_from_str = "parse_scheme"))]
+        scheme: scheme::StealthScheme,
+        /// Hash of the nonce broadcasting
+        /// tx, recorded in the wallet index
+        #[structopt(long = "tx-hash")]
+        tx_hash: Option<String>,
+        /// Block of the nonce
+        /// broadcasting tx
+        #[structopt(long = "block")]
+        block: Option<u64>
+    },
+    /// Migrate legacy plaintext
+    /// key files to the keystore format
+    #[structopt(name = "migrate")]
+    Migrate {
+        /// Directory in which the
+        /// legacy key files are saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>
     },
-    /// Scan the blockchain
-    /// for incoming txs
+    /// Manage the daemon scanning
+    /// the blockchain for incoming txs
     #[structopt(name = "scan")]
     Scan {
+        #[structopt(subcommand)]
+        cmd: ScanCommand
+    },
+    /// Scan a historical block range
+    /// in parallel, in the foreground
+    #[structopt(name = "backfill")]
+    Backfill {
         /// Directory in which master
         /// keypair file is saved
         #[structopt(short = "s", parse(from_os_str))]
@@ -86,37 +213,464 @@ enum Cli {
         /// Master key address
         #[structopt(short = "a")]
         address: String,
-        /// Block number to
-        /// scan from
-        #[structopt(short = "b")]
-        block: Option<u64>
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+        /// Block number to backfill
+        /// from, overrides the checkpoint
+        #[structopt(short = "b", long = "from")]
+        block: Option<u64>,
+        /// Last block to backfill
+        /// (the confirmed head if absent)
+        #[structopt(long = "to")]
+        to_block: Option<u64>,
+        /// Blocks an announcement must be
+        /// buried under before it is processed
+        #[structopt(long = "confirmations", default_value = "12")]
+        confirmations: u64,
+        /// Blocks fetched and scanned
+        /// per chunk, one chunk per core
+        #[structopt(long = "chunk-size", default_value = "5000")]
+        chunk_size: u64,
+        /// JSON config file of the scanner
+        /// (labels and notifications)
+        #[structopt(long = "config", parse(from_os_str))]
+        config_file: Option<PathBuf>,
+        /// Source of announcements,
+        /// subgraph, rpc (eth_getLogs) or dump
+        #[structopt(long = "source", default_value = "subgraph", parse(try_from_str = "parse_source"))]
+        source: source::SourceKind,
+        /// JSONL or CSV announcement
+        /// dump, for the dump source
+        #[structopt(long = "dump-file", parse(from_os_str))]
+        dump_file: Option<PathBuf>
+    },
+    /// Show the received payments
+    /// and the sent transfers
+    #[structopt(name = "history")]
+    History {
+        /// Directory in which the
+        /// payments database is saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// Master key (payments)
+        /// or sender (transfers)
+        #[structopt(short = "a")]
+        address: Option<String>,
+        /// Status: detected, confirmed, swept,
+        /// orphaned or sent (transfers)
+        #[structopt(long = "status", parse(try_from_str = "parse_status"))]
+        status: Option<payments::PaymentStatus>,
+        /// First day to show
+        /// (YYYY-MM-DD, UTC)
+        #[structopt(long = "since", parse(try_from_str = "parse_date"))]
+        since: Option<i64>,
+        /// Last day to show
+        /// (YYYY-MM-DD, UTC)
+        #[structopt(long = "until", parse(try_from_str = "parse_date"))]
+        until: Option<i64>,
+        /// Output format, table,
+        /// json or csv
+        #[structopt(long = "format", default_value = "table", parse(try_from_str = "parse_format"))]
+        format: payments::HistoryFormat
+    }
+}
+
+#[derive(StructOpt, Debug)]
+enum ScanCommand {
+    /// Start the scan daemon
+    #[structopt(name = "start")]
+    Start {
+        /// Directory in which master
+        /// keypair file is saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// Master key address,
+        /// repeat for several keys
+        #[structopt(short = "a")]
+        address: Vec<String>,
+        /// Scan for all master keys
+        /// in the storage dir
+        #[structopt(long = "all")]
+        all: bool,
+        /// File containing the keystore
+        /// passphrase (prompted if absent)
+        #[structopt(short = "p", parse(from_os_str))]
+        password_file: Option<PathBuf>,
+        /// Block number to scan from,
+        /// overrides the checkpoint
+        #[structopt(short = "b", long = "from")]
+        block: Option<u64>,
+        /// Discard the stored
+        /// scan checkpoint
+        #[structopt(long = "reset")]
+        reset: bool,
+        /// Blocks an announcement must be
+        /// buried under before it is processed
+        #[structopt(long = "confirmations", default_value = "12")]
+        confirmations: u64,
+        /// Consecutive failed scans
+        /// tolerated before exiting
+        #[structopt(long = "error-budget", default_value = "10")]
+        error_budget: u32,
+        /// Source of announcements,
+        /// subgraph, rpc (eth_getLogs) or dump
+        #[structopt(long = "source", default_value = "subgraph", parse(try_from_str = "parse_source"))]
+        source: source::SourceKind,
+        /// JSONL or CSV announcement
+        /// dump, for the dump source
+        #[structopt(long = "dump-file", parse(from_os_str))]
+        dump_file: Option<PathBuf>,
+        /// Pid file of the daemon
+        /// (<storage_dir>/scan.pid)
+        #[structopt(long = "pid-file", parse(from_os_str))]
+        pid_file: Option<PathBuf>,
+        /// Directory of the daemon logs
+        /// (<storage_dir>/logs)
+        #[structopt(long = "log-dir", parse(from_os_str))]
+        log_dir: Option<PathBuf>,
+        /// Status socket of the daemon
+        /// (<storage_dir>/scan.sock)
+        #[structopt(long = "socket", parse(from_os_str))]
+        socket: Option<PathBuf>,
+        /// Stay in the foreground and log
+        /// to stdout and stderr (systemd)
+        #[structopt(long = "foreground")]
+        foreground: bool,
+        /// JSON config file, reloaded
+        /// along with the keys on SIGHUP
+        #[structopt(long = "config", parse(from_os_str))]
+        config_file: Option<PathBuf>,
+        /// What starts a scan, the schedule or
+        /// new-heads (pushed by the node)
+        #[structopt(long = "trigger", default_value = "schedule", parse(try_from_str = "parse_trigger"))]
+        trigger: scan::Trigger,
+        /// Cron expression of the schedule
+        /// (sec min hour day month weekday)
+        #[structopt(long = "schedule", default_value = "1/10 * * * * *")]
+        schedule: String,
+        /// WebSocket endpoint of the node,
+        /// for the new-heads trigger
+        #[structopt(long = "ws-url", default_value = "ws://127.0.0.1:8546")]
+        ws_url: String
+    },
+    /// Stop the running scan daemon
+    #[structopt(name = "stop")]
+    Stop {
+        /// Directory in which master
+        /// keypair file is saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// Pid file of the daemon
+        /// (<storage_dir>/scan.pid)
+        #[structopt(long = "pid-file", parse(from_os_str))]
+        pid_file: Option<PathBuf>,
+        /// Status socket of the daemon
+        /// (<storage_dir>/scan.sock)
+        #[structopt(long = "socket", parse(from_os_str))]
+        socket: Option<PathBuf>
+    },
+    /// Show the health and progress
+    /// of the running scan daemon
+    #[structopt(name = "status")]
+    Status {
+        /// Directory in which master
+        /// keypair file is saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// Status socket of the daemon
+        /// (<storage_dir>/scan.sock)
+        #[structopt(long = "socket", parse(from_os_str))]
+        socket: Option<PathBuf>,
+        /// Print the status as JSON
+        #[structopt(long = "json")]
+        json: bool
+    },
+    /// Show the last lines of
+    /// the scan daemon logs
+    #[structopt(name = "logs")]
+    Logs {
+        /// Directory in which master
+        /// keypair file is saved
+        #[structopt(short = "s", parse(from_os_str))]
+        storage_dir: PathBuf,
+        /// Directory of the daemon logs
+        /// (<storage_dir>/logs)
+        #[structopt(long = "log-dir", parse(from_os_str))]
+        log_dir: Option<PathBuf>,
+        /// Number of lines
+        /// shown per log file
+        #[structopt(short = "n", default_value = "50")]
+        lines: usize
+    }
+}
+
+fn parse_scheme(s: &str) -> Result<scheme::StealthScheme, String> {
+    s.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_source(s: &str) -> Result<source::SourceKind, String> {
+    s.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_trigger(s: &str) -> Result<scan::Trigger, String> {
+    s.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_status(s: &str) -> Result<payments::PaymentStatus, String> {
+    s.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_format(s: &str) -> Result<payments::HistoryFormat, String> {
+    s.parse().map_err(|e| format!("{:?}", e))
+}
+
+fn parse_date(s: &str) -> Result<i64, String> {
+    chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_hms(0, 0, 0).timestamp())
+        .map_err(|e| format!("{:?}", e))
+}
+
+fn read_passphrase(password_file: &Option<PathBuf>, confirm: bool) -> String {
+    match utils::read_passphrase(password_file, confirm) {
+        Ok(passphrase) => passphrase,
+        Err(error) => panic!("[Error in reading passphrase]: {:?}", error)
+    }
+}
+
+fn print_master_keys(master_keys: Result<key::MasterKeys, errors::Error>, context: &str) {
+    match master_keys {
+        Ok(master_keys) => {
+            println!("Address: {:?}", master_keys.spend.address());
+            let meta_address = meta_address::StealthMetaAddress::new(
+                meta_address::DEFAULT_CHAIN,
+                master_keys.spend.public(),
+                master_keys.scan.public()
+            );
+            println!("Meta-address (share with senders): {}", meta_address);
+        },
+        Err(error) => panic!("{}: {:?}", context, error)
+    }
+}
+
+fn print_key_entry(entry: &list::KeyEntry) {
+    let kind = match entry.kind {
+        list::KeyKind::Master => "master",
+        list::KeyKind::Scan => "scan",
+        list::KeyKind::Stealth => "stealth",
+        list::KeyKind::Unknown => "unknown"
+    };
+    let legacy = if entry.legacy { " (legacy, run `migrate`)" } else { "" };
+    println!("[{}] {}{}", kind, entry.address, legacy);
+    if let Some(public_key) = &entry.public_key {
+        println!("    Public Key (Compressed): {}", public_key);
+    }
+    if let Some(meta_address) = &entry.meta_address {
+        println!("    Meta-address: {}", meta_address);
+    }
+    if let Some(master_address) = &entry.master_address {
+        println!("    Master address: {}", master_address);
+    }
+    if let Some(nonce_point) = &entry.nonce_point {
+        println!("    Nonce point: {}", nonce_point);
+    }
+    if let Some(announcement) = &entry.announcement {
+        let orphaned = if entry.orphaned { ", orphaned by a reorg" } else { "" };
+        println!("    Announcement: {} (block {}{})", announcement.tx_hash, announcement.block, orphaned);
+    }
+    if let Some(balance) = &entry.balance {
+        println!("    Balance: {}", balance);
+    }
+}
+
+fn print_history(entries: &[payments::HistoryEntry], format: payments::HistoryFormat) -> Result<(), errors::Error> {
+    match format {
+        payments::HistoryFormat::Json => println!("{}", serde_json::to_string_pretty(&entries)?),
+        payments::HistoryFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(std::io::stdout());
+            for entry in entries {
+                writer.serialize(entry)?;
+            }
+            writer.flush()?;
+        },
+        payments::HistoryFormat::Table => {
+            println!(
+                "{:<19}  {:<3}  {:<42}  {:<42}  {:>24}  {:<9}  {}",
+                "DATE", "DIR", "KEY", "STEALTH ADDRESS", "AMOUNT (WEI)", "STATUS", "ANNOUNCEMENT TX"
+            );
+            for entry in entries {
+                println!(
+                    "{:<19}  {:<3}  {:<42}  {:<42}  {:>24}  {:<9}  {}",
+                    entry.date,
+                    entry.direction,
+                    entry.key,
+                    entry.stealth_address,
+                    entry.amount.as_ref().map_or("unknown", |amount| amount.as_str()),
+                    entry.status,
+                    entry.announcement_tx
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_scan_status(status: &daemon::ScanStatus) {
+    println!("Pid: {}", status.pid);
+    println!("Started at: {}", status.started_at);
+    println!("Health: {}", status.health);
+    if let Some(last_error) = &status.last_error {
+        println!("Last error ({} failed attempts): {}", status.consecutive_failures, last_error);
+    }
+    for key in &status.keys {
+        println!("Master address: {}", key.master_address);
+        println!("    Last block: {}", key.last_block);
+        println!("    Pending matches: {}", key.pending);
+        if let Some(last_match) = &key.last_match {
+            println!("    Last match: {} (block {})", last_match.tx_hash, last_match.block);
+        }
+    }
+}
+
+fn handle_scan(cmd: ScanCommand) {
+    match cmd {
+        ScanCommand::Start {
+            storage_dir, address, all, password_file, block, reset, confirmations,
+            error_budget, source, dump_file, pid_file, log_dir, socket, foreground, config_file,
+            trigger, schedule, ws_url
+        } => {
+            println!("Handle Scan");
+            let source = match source::new(source, &dump_file) {
+                Ok(source) => source,
+                Err(error) => panic!("[Error in announcement source]: {:?}", error)
+            };
+            let paths = daemon::DaemonPaths::new(&storage_dir, pid_file, log_dir, socket);
+            let options = scan::ScanOptions {
+                master_addresses: address,
+                all: all,
+                from_block: block,
+                reset: reset,
+                confirmations: confirmations,
+                error_budget: error_budget,
+                config_file: config_file,
+                foreground: foreground,
+                trigger: trigger,
+                schedule: schedule,
+                ws_url: ws_url
+            };
+            let passphrase = read_passphrase(&password_file, false);
+            if let Err(error) = scan::scan(&storage_dir, &passphrase, &options, &paths, source) {
+                panic!("[Error in scan]: {:?}", error);
+            }
+        },
+        ScanCommand::Stop { storage_dir, pid_file, socket } => {
+            let paths = daemon::DaemonPaths::new(&storage_dir, pid_file, None, socket);
+            match daemon::stop(&paths) {
+                Ok(Some(pid)) => println!("Stopped scan daemon {}", pid),
+                Ok(None) => println!("Scan daemon was not running, removed its stale pid file"),
+                Err(error) => panic!("[Error in stopping scan]: {:?}", error)
+            }
+        },
+        ScanCommand::Status { storage_dir, socket, json } => {
+            let paths = daemon::DaemonPaths::new(&storage_dir, None, None, socket);
+            match daemon::status(&paths) {
+                Ok(status) => {
+                    if json {
+                        match serde_json::to_string_pretty(&status) {
+                            Ok(content) => println!("{}", content),
+                            Err(error) => panic!("[Error in scan status]: {:?}", error)
+                        }
+                    } else {
+                        print_scan_status(&status);
+                    }
+                },
+                Err(error) => panic!("[Error in scan status]: {:?}", error)
+            }
+        },
+        ScanCommand::Logs { storage_dir, log_dir, lines } => {
+            let paths = daemon::DaemonPaths::new(&storage_dir, None, log_dir, None);
+            match daemon::logs(&paths, lines) {
+                Ok(logs) => {
+                    for (path, lines) in logs {
+                        println!("==> {} <==", path.display());
+                        for line in lines {
+                            println!("{}", line);
+                        }
+                    }
+                },
+                Err(error) => panic!("[Error in scan logs]: {:?}", error)
+            }
+        }
     }
 }
 
 fn main() {
     match Cli::from_args() {
-        Cli::Create { storage_dir } => {
+        Cli::Create { storage_dir, password_file, mnemonic, account_path } => {
             println!("Handle Create {:?}", storage_dir);
-            if let Err(error) = key::new(&storage_dir) {
-                panic!("[Error in creating/storing keypair]: {:?}", error)
+            let passphrase = read_passphrase(&password_file, true);
+            let master_keys = if mnemonic {
+                let phrase = hd::new_mnemonic();
+                println!("Mnemonic (write it down, it is not stored): {}", phrase);
+                key::new_from_mnemonic(&storage_dir, &passphrase, &phrase, &account_path, false)
+            } else {
+                key::new(&storage_dir, &passphrase)
+            };
+            print_master_keys(master_keys, "[Error in creating/storing keypair]");
+        },
+        Cli::Restore { storage_dir, password_file, mnemonic_file, account_path, force } => {
+            println!("Handle Restore {:?}", storage_dir);
+            let phrase = match utils::read_mnemonic(&mnemonic_file) {
+                Ok(phrase) => phrase,
+                Err(error) => panic!("[Error in reading mnemonic]: {:?}", error)
+            };
+            let passphrase = read_passphrase(&password_file, true);
+            let master_keys = key::new_from_mnemonic(&storage_dir, &passphrase, &phrase, &account_path, force);
+            print_master_keys(master_keys, "[Error in restoring keypair]");
+        },
+        Cli::List { storage_dir, balances, json } => {
+            match list::list(&storage_dir, balances) {
+                Ok(entries) => {
+                    if json {
+                        match serde_json::to_string_pretty(&entries) {
+                            Ok(content) => println!("{}", content),
+                            Err(error) => panic!("[Error in listing keypairs]: {:?}", error)
+                        }
+                    } else {
+                        for entry in entries {
+                            print_key_entry(&entry);
+                        }
+                    }
+                },
+                Err(error) => panic!("[Error in listing keypairs]: {:?}", error)
             }
         },
-        Cli::List { storage_dir } => println!("Handle List {:?}", storage_dir),
-        Cli::Transfer { storage_dir, from, to, value } => {
+        Cli::Transfer { storage_dir, from, password_file, to, chain, scheme, value } => {
             println!("Handle Transfer [dir] = {:?}, [from] = {}, [to] = {}, value = {}", storage_dir, from, to, value);
-            match transfer::transfer(&storage_dir, &from, &to, &value) {
+            let passphrase = read_passphrase(&password_file, false);
+            let scheme = scheme.unwrap_or_else(|| scheme::StealthScheme::for_recipient(&to));
+            match transfer::transfer(&storage_dir, &from, &passphrase, &chain, scheme, &to, &value) {
                 Ok(transfer_receipt) => {
                     println!("Successfully transferred");
                     println!("Transfer tx hash: {:?}", transfer_receipt.tx1_hash);
                     println!("Nonce broadcasted tx hash: {:?}", transfer_receipt.tx2_hash);
+                    println!("Stealth scheme: {}", transfer_receipt.scheme);
                     println!("Share this nonce point with recipient: {}", transfer_receipt.nonce_point);
                 },
                 Err(error) => panic!("[Error in transfer]: {:?}", error)
             }
         },
-        Cli::Receive { storage_dir, address, nonce_point } => {
+        Cli::Receive { storage_dir, address, password_file, nonce_point, scheme, tx_hash, block } => {
             println!("Handle receive [dir] = {:?}, [master] = {}, [nonce point] = {:?}", storage_dir, address, nonce_point);
-            match receive::receive(&storage_dir, &address, &nonce_point) {
+            let passphrase = read_passphrase(&password_file, false);
+            let announcement = match (tx_hash, block) {
+                (Some(tx_hash), Some(block)) => Some(index::Announcement { tx_hash: tx_hash, block: block }),
+                (None, None) => None,
+                _ => panic!("[Error in receiving]: --tx-hash and --block must be given together")
+            };
+            match receive::receive(&storage_dir, &address, &passphrase, &nonce_point, scheme, announcement.as_ref()) {
                 Ok(receipt) => {
                     println!("Successfully claimed receipt");
                     println!("Recipient address: {:?}", receipt.address);
@@ -125,10 +679,46 @@ fn main() {
                 Err(error) => panic!("[Error in receiving]: {:?}", error)
             }
         },
-        Cli::Scan { storage_dir, address, block } => {
-            println!("Handle Scan");
-            if let Err(error) = scan::scan(&storage_dir, &address, block) {
-                panic!("[Error in scan]: {:?}", error);
+        Cli::Migrate { storage_dir, password_file } => {
+            println!("Handle Migrate {:?}", storage_dir);
+            let passphrase = read_passphrase(&password_file, true);
+            match migrate::migrate(&storage_dir, &passphrase) {
+                Ok(report) => {
+                    for entry in report.entries {
+                        match entry.status {
+                            migrate::MigrationStatus::Migrated => println!("Migrated: {} ({})", entry.file, entry.address.unwrap_or_default()),
+                            migrate::MigrationStatus::Skipped => println!("Skipped: {}", entry.file),
+                            migrate::MigrationStatus::Failed => println!("Failed: {} ({})", entry.file, entry.error.unwrap_or_default())
+                        }
+                    }
+                },
+                Err(error) => panic!("[Error in migration]: {:?}", error)
+            }
+        },
+        Cli::Scan { cmd } => handle_scan(cmd),
+        Cli::Backfill { storage_dir, address, password_file, block, to_block, confirmations, chunk_size, config_file, source, dump_file } => {
+            println!("Handle Backfill");
+            let source = match source::new(source, &dump_file) {
+                Ok(source) => source,
+                Err(error) => panic!("[Error in announcement source]: {:?}", error)
+            };
+            let passphrase = read_passphrase(&password_file, false);
+            if let Err(error) = backfill::backfill(&storage_dir, &address, &passphrase, block, to_block, confirmations, chunk_size, &config_file, source) {
+                panic!("[Error in backfill]: {:?}", error);
+            }
+        },
+        Cli::History { storage_dir, address, status, since, until, format } => {
+            let filter = payments::HistoryFilter {
+                key: address,
+                status: status,
+                since: since,
+                // the last day is included
+                until: until.map(|until| until + 24 * 60 * 60)
+            };
+            let printed = payments::history(&storage_dir, &filter)
+                .and_then(|entries| print_history(&entries, format));
+            if let Err(error) = printed {
+                panic!("[Error in history]: {:?}", error);
             }
         }
     }
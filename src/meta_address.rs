@@ -0,0 +1,154 @@
+use std::fmt;
+use std::str::FromStr;
+
+use parity_crypto::Keccak256;
+use parity_crypto::publickey::Public;
+
+use crate::errors::Error;
+use crate::key;
+
+/// Chain short name (EIP-3770) used when none is given
+pub const DEFAULT_CHAIN: &str = "eth";
+
+/// EIP-5564 stealth meta-address, `st:<chain>:0x<spend><view>`
+/// with both public keys in compressed form. The hex part is
+/// checksummed by its letter case, similar to EIP-55 addresses
+#[derive(Clone, Debug, PartialEq)]
+pub struct StealthMetaAddress {
+    pub chain: String,
+    pub spend_public: Public,
+    pub scan_public: Public,
+}
+
+impl StealthMetaAddress {
+    pub fn new(chain: &str, spend_public: &Public, scan_public: &Public) -> StealthMetaAddress {
+        StealthMetaAddress {
+            chain: String::from(chain),
+            spend_public: *spend_public,
+            scan_public: *scan_public
+        }
+    }
+
+    fn keys_hex(&self) -> Result<String, Error> {
+        let spend = key::to_secp256k1_public(&self.spend_public)?;
+        let scan = key::to_secp256k1_public(&self.scan_public)?;
+        Ok(format!("{}{}", spend, scan))
+    }
+}
+
+impl fmt::Display for StealthMetaAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let keys_hex = self.keys_hex().map_err(|_| fmt::Error)?;
+        write!(f, "st:{}:0x{}", self.chain, to_checksum(&keys_hex))
+    }
+}
+
+impl FromStr for StealthMetaAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // bare public keys (without the `st:<chain>:`
+        // prefix) are accepted for the default chain
+        let (chain, keys_hex) = match s.strip_prefix("st:") {
+            Some(rest) => {
+                let mut parts = rest.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(chain), Some(keys_hex)) if !chain.is_empty() => (chain, keys_hex),
+                    _ => return Err(Error::InvalidMetaAddress)
+                }
+            },
+            None => (DEFAULT_CHAIN, s)
+        };
+        let keys_hex = keys_hex.trim_start_matches("0x");
+
+        // mixed case hex must match its checksum
+        let lowercase = keys_hex.to_lowercase();
+        let uppercase = keys_hex.to_uppercase();
+        if keys_hex != lowercase && keys_hex != uppercase && keys_hex != to_checksum(&lowercase) {
+            return Err(Error::InvalidMetaAddress);
+        }
+
+        let (spend_public, scan_public) = match lowercase.len() {
+            // a single master public key acts
+            // as both the spend and scan key
+            66 => {
+                let (public_key, _) = key::public_key_from_str(&lowercase)?;
+                (public_key, public_key)
+            },
+            132 => {
+                let (spend_public, _) = key::public_key_from_str(&lowercase[0..66])?;
+                let (scan_public, _) = key::public_key_from_str(&lowercase[66..132])?;
+                (spend_public, scan_public)
+            },
+            _ => return Err(Error::InvalidMetaAddress)
+        };
+
+        Ok(StealthMetaAddress::new(chain, &spend_public, &scan_public))
+    }
+}
+
+fn to_checksum(lowercase_hex: &str) -> String {
+    // uppercase a letter if the matching nibble of the
+    // keccak hash of the lowercase hex is at least 8
+    let hash = lowercase_hex.as_bytes().keccak256();
+    lowercase_hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let byte = hash[(i % 64) / 2];
+            let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+            if nibble >= 8 { c.to_ascii_uppercase() } else { c }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parity_crypto::publickey::{Generator, Random};
+
+    #[test]
+    fn test_format_and_parse() {
+        let spend = Random.generate();
+        let scan = Random.generate();
+        let meta_address = StealthMetaAddress::new(DEFAULT_CHAIN, spend.public(), scan.public());
+        let formatted = meta_address.to_string();
+        assert!(formatted.starts_with("st:eth:0x"));
+        assert!(formatted.len() == 9 + 132);
+
+        // checksummed, lowercase and bare forms all parse
+        let keys_hex = &formatted[9..];
+        assert!(StealthMetaAddress::from_str(&formatted).unwrap() == meta_address);
+        assert!(StealthMetaAddress::from_str(&formatted.to_lowercase()).unwrap() == meta_address);
+        assert!(StealthMetaAddress::from_str(keys_hex).unwrap() == meta_address);
+
+        let sepolia = StealthMetaAddress::from_str(&format!("st:sep:0x{}", keys_hex)).unwrap();
+        assert!(sepolia.chain == "sep");
+    }
+
+    #[test]
+    fn test_invalid_meta_address() {
+        let spend = Random.generate();
+        let meta_address = StealthMetaAddress::new(DEFAULT_CHAIN, spend.public(), spend.public());
+        let lowercase = meta_address.to_string().to_lowercase();
+
+        // flipping the case of a single letter breaks the checksum
+        let position = lowercase[9..].find(|c: char| c.is_ascii_alphabetic()).unwrap() + 9;
+        let mut broken = meta_address.to_string();
+        let flipped = if broken[position..position + 1] == lowercase[position..position + 1] {
+            broken[position..position + 1].to_uppercase()
+        } else {
+            broken[position..position + 1].to_lowercase()
+        };
+        broken.replace_range(position..position + 1, &flipped);
+        assert!(StealthMetaAddress::from_str(&broken).is_err());
+
+        assert!(StealthMetaAddress::from_str(&lowercase[0..lowercase.len() - 2]).is_err());
+        assert!(StealthMetaAddress::from_str(&format!("st::{}", &lowercase[7..])).is_err());
+
+        // a single public key is accepted for both keys
+        let single = StealthMetaAddress::from_str(&lowercase[9..75]).unwrap();
+        assert!(single.spend_public == single.scan_public);
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::errors::Error;
+use crate::key;
+use crate::key::KeyFile;
+use crate::utils::unix_timestamp;
+
+#[derive(Serialize, Debug)]
+pub struct MigrationReport {
+    pub timestamp: u64,
+    pub entries: Vec<MigrationEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MigrationEntry {
+    pub file: String,
+    pub address: Option<String>,
+    pub status: MigrationStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationStatus {
+    Migrated,
+    Skipped,
+    Failed,
+}
+
+pub fn migrate(storage_dir: &PathBuf, passphrase: &str) -> Result<MigrationReport, Error> {
+    let mut entries = vec![];
+
+    // go through every key file in the storage dir
+    let mut paths: Vec<PathBuf> = fs::read_dir(storage_dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        let file = path.file_name().unwrap().to_string_lossy().into_owned();
+        let entry = match migrate_file(&path, &passphrase) {
+            Ok(Some(address)) => MigrationEntry {
+                file: file,
+                address: Some(address),
+                status: MigrationStatus::Migrated,
+                error: None
+            },
+            Ok(None) => MigrationEntry {
+                file: file,
+                address: None,
+                status: MigrationStatus::Skipped,
+                error: None
+            },
+            Err(e) => MigrationEntry {
+                file: file,
+                address: None,
+                status: MigrationStatus::Failed,
+                error: Some(format!("{:?}", e))
+            }
+        };
+        entries.push(entry);
+    }
+
+    let timestamp = unix_timestamp();
+    let report = MigrationReport {
+        timestamp: timestamp,
+        entries: entries
+    };
+
+    // leave the report in the storage dir
+    let mut report_path = storage_dir.clone();
+    report_path.push(format!("migration-report-{}.log", timestamp));
+    let mut report_file = File::create(report_path)?;
+    write!(report_file, "{}", serde_json::to_string_pretty(&report)?)?;
+
+    Ok(report)
+}
+
+fn migrate_file(path: &PathBuf, passphrase: &str) -> Result<Option<String>, Error> {
+    // only legacy plaintext files are converted
+    let content = fs::read_to_string(path)?;
+    if !key::is_legacy(&content) {
+        return Ok(None);
+    }
+    let keypair = key::parse_legacy(&content)?;
+    let address = format!("{:?}", keypair.address());
+
+    // legacy files are always named after their address
+    if path.file_stem().map_or(true, |stem| stem.to_string_lossy().to_lowercase() != address) {
+        return Err(Error::Custom(String::from("File name does not match the key address")));
+    }
+
+    // encrypt and verify the round trip before
+    // replacing the legacy file with the new one
+    let key_file = key::encrypt(&keypair, &passphrase)?;
+    let serialized = serde_json::to_string_pretty(&key_file)?;
+    let key_file: KeyFile = serde_json::from_str(&serialized)?;
+    let decrypted_keypair = key::decrypt(&key_file, &passphrase)?;
+    if decrypted_keypair.address() != keypair.address() {
+        return Err(Error::Custom(String::from("Round trip derived a different address")));
+    }
+
+    key::write_private(&path, &serialized)?;
+
+    Ok(Some(address))
+}
+
+#[cfg(test)]
+mod tests {
+    use parity_crypto::publickey::{Generator, Random};
+
+    use super::*;
+
+    #[test]
+    fn test_migrate() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        // a legacy plaintext key file, named after its address
+        let keypair = Random.generate();
+        let address = format!("{:?}", keypair.address());
+        let mut path = storage_dir.clone();
+        path.push(format!("{}.json", address));
+        fs::write(&path, format!(
+            "Secret Key: {:x}\nPublic Key (Uncompressed): {:x}\nPublic Key (Compressed): {}\nAddress: {:?}",
+            keypair.secret(), keypair.public(), key::to_secp256k1_public(keypair.public()).unwrap(), keypair.address()
+        )).unwrap();
+
+        let report = migrate(&storage_dir, "passphrase").unwrap();
+        assert!(report.entries.len() == 1 && report.entries[0].status == MigrationStatus::Migrated);
+        assert!(report.entries[0].address == Some(address));
+
+        // the v3 file decrypts to the same secret
+        let key_file: KeyFile = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(key::decrypt(&key_file, "passphrase").unwrap().secret() == keypair.secret());
+
+        let mut report_path = storage_dir.clone();
+        report_path.push(format!("migration-report-{}.log", report.timestamp));
+        assert!(report_path.exists());
+
+        // migrated files are skipped when run again
+        let report = migrate(&storage_dir, "passphrase").unwrap();
+        assert!(report.entries[0].status == MigrationStatus::Skipped);
+    }
+}
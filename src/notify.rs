@@ -0,0 +1,157 @@
+use std::cmp;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use web3::types::H160;
+
+use crate::client::Client;
+use crate::errors::Error;
+use crate::scheme::StealthScheme;
+
+// attempts at notifying a sink before it is given up
+pub const MAX_NOTIFY_ATTEMPTS: u32 = 10;
+
+// delay before the first retry, doubled
+// after every further failed attempt
+const BASE_RETRY_SECS: u64 = 30;
+const MAX_RETRY_SECS: u64 = 3600;
+
+/// Where detected payments are reported, e.g.
+/// `{"webhook": "https://accounting.local/payments"}`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationSink {
+    /// Shell command, run with the payment in `RS_TX_*` env vars
+    Command(String),
+    /// URL the payment is POSTed to as JSON
+    Webhook(String),
+    /// File the payment is appended to as a JSON line
+    Jsonl(PathBuf),
+}
+
+/// Payment detected by the scanner
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PaymentNotification {
+    pub master_address: String,
+    /// Label of the master key in the config,
+    /// its address if it has none
+    pub label: String,
+    pub stealth_address: H160,
+    /// Balance of the stealth address in wei,
+    /// when it could be fetched
+    pub amount: Option<String>,
+    pub tx_hash: String,
+    pub block: u64,
+    pub nonce_point: String,
+    pub scheme: StealthScheme,
+    /// Whether the stealth key was derived and stored,
+    /// or is left to be claimed with `receive`
+    pub claimed: bool,
+}
+
+/// Reports the payment to every sink, returns
+/// the ones that could not be notified
+pub fn notify<'a>(
+    client: &Client,
+    sinks: &[&'a NotificationSink],
+    notification: &PaymentNotification
+) -> Vec<&'a NotificationSink> {
+    let mut failed = vec![];
+    for sink in sinks {
+        let result = match sink {
+            NotificationSink::Command(command) => run_command(&command, &notification),
+            NotificationSink::Webhook(url) => post_webhook(&client, &url, &notification),
+            NotificationSink::Jsonl(path) => append_jsonl(&path, &notification)
+        };
+        if let Err(e) = result {
+            eprintln!("[notify] Error notifying {:?} of {}: {:?}", sink, notification.tx_hash, e);
+            failed.push(*sink);
+        }
+    }
+
+    failed
+}
+
+/// Delay before retrying the sinks that failed `attempts` times
+pub fn retry_delay(attempts: u32) -> u64 {
+    let exponent = cmp::min(attempts.saturating_sub(1), 16);
+    cmp::min(BASE_RETRY_SECS << exponent, MAX_RETRY_SECS)
+}
+
+fn run_command(command: &str, notification: &PaymentNotification) -> Result<(), Error> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RS_TX_MASTER_ADDRESS", &notification.master_address)
+        .env("RS_TX_LABEL", &notification.label)
+        .env("RS_TX_STEALTH_ADDRESS", format!("{:?}", notification.stealth_address))
+        .env("RS_TX_AMOUNT", notification.amount.clone().unwrap_or_default())
+        .env("RS_TX_TX_HASH", &notification.tx_hash)
+        .env("RS_TX_BLOCK", notification.block.to_string())
+        .env("RS_TX_NONCE_POINT", &notification.nonce_point)
+        .env("RS_TX_SCHEME", notification.scheme.to_string())
+        .env("RS_TX_CLAIMED", notification.claimed.to_string())
+        .status()?;
+
+    if !status.success() {
+        return Err(Error::Custom(format!("Notification command exited with {}", status)));
+    }
+
+    Ok(())
+}
+
+fn post_webhook(client: &Client, url: &str, notification: &PaymentNotification) -> Result<(), Error> {
+    client.http
+        .post(url)
+        .json(&notification)
+        .send()?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+fn append_jsonl(path: &PathBuf, notification: &PaymentNotification) -> Result<(), Error> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&notification)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_jsonl() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("payments.jsonl");
+
+        let notification = PaymentNotification {
+            master_address: String::from("0xaa"),
+            label: String::from("shop"),
+            stealth_address: H160::from_low_u64_be(1),
+            amount: Some(String::from("1000")),
+            tx_hash: String::from("0xabcd"),
+            block: 42,
+            nonce_point: String::from("02cc"),
+            scheme: StealthScheme::RsTx,
+            claimed: false
+        };
+        append_jsonl(&path, &notification).unwrap();
+        append_jsonl(&path, &notification).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<PaymentNotification> = content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert!(lines.len() == 2 && lines[1].label == "shop" && lines[1].block == 42);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        assert!(retry_delay(1) == 30 && retry_delay(2) == 60);
+        assert!(retry_delay(MAX_NOTIFY_ATTEMPTS) == MAX_RETRY_SECS);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::errors::Error;
+
+// AES-GCM ciphertext of a 20 byte address with its 16 byte tag
+const ENCRYPTED_ADDRESS_LEN: usize = 36;
+
+// AES-GCM nonce length
+const NONCE_LEN: usize = 12;
+
+/// Payloads before versioning, whose AES-GCM nonce is
+/// derived from the account nonce of the broadcasting tx
+pub const VERSION_LEGACY: u8 = 0;
+
+/// AES-GCM nonce of legacy payloads, the first 12 bytes of the 32 byte
+/// big endian account nonce. Account nonces fit in 64 bits, so it is zero
+pub const LEGACY_NONCE: [u8; NONCE_LEN] = [0u8; NONCE_LEN];
+
+/// Version byte, view tag, random nonce, ciphertext
+pub const VERSION_RANDOM_NONCE: u8 = 1;
+
+/// Payload broadcasted as `encryptedRecipient` to the RsTx contract
+#[derive(Clone, Debug, PartialEq)]
+pub struct RsTxPayload {
+    pub version: u8,
+    pub view_tag: Option<u8>,
+    /// AES-GCM nonce, `None` for legacy payloads
+    pub nonce: Option<[u8; NONCE_LEN]>,
+    pub ciphertext: Vec<u8>,
+}
+
+impl RsTxPayload {
+    pub fn new(view_tag: u8, nonce: [u8; NONCE_LEN], ciphertext: Vec<u8>) -> RsTxPayload {
+        RsTxPayload {
+            version: VERSION_RANDOM_NONCE,
+            view_tag: Some(view_tag),
+            nonce: Some(nonce),
+            ciphertext: ciphertext
+        }
+    }
+
+    /// AES-GCM nonce the ciphertext was encrypted with
+    pub fn encryption_nonce(&self) -> [u8; NONCE_LEN] {
+        self.nonce.unwrap_or(LEGACY_NONCE)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.ciphertext.len() + NONCE_LEN + 2);
+        if self.version != VERSION_LEGACY {
+            bytes.push(self.version);
+        }
+        if let Some(view_tag) = self.view_tag {
+            bytes.push(view_tag);
+        }
+        if let Some(nonce) = self.nonce {
+            bytes.extend_from_slice(&nonce);
+        }
+        bytes.extend_from_slice(&self.ciphertext);
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<RsTxPayload, Error> {
+        // legacy payloads have no version byte, they are only
+        // the encrypted address, optionally prefixed by a view tag
+        match bytes.len() {
+            ENCRYPTED_ADDRESS_LEN => Ok(RsTxPayload {
+                version: VERSION_LEGACY,
+                view_tag: None,
+                nonce: None,
+                ciphertext: bytes.to_vec()
+            }),
+            len if len == ENCRYPTED_ADDRESS_LEN + 1 => Ok(RsTxPayload {
+                version: VERSION_LEGACY,
+                view_tag: Some(bytes[0]),
+                nonce: None,
+                ciphertext: bytes[1..].to_vec()
+            }),
+            len if len == ENCRYPTED_ADDRESS_LEN + NONCE_LEN + 2 && bytes[0] == VERSION_RANDOM_NONCE => {
+                let mut nonce = [0u8; NONCE_LEN];
+                nonce.copy_from_slice(&bytes[2..2 + NONCE_LEN]);
+                Ok(RsTxPayload::new(bytes[1], nonce, bytes[2 + NONCE_LEN..].to_vec()))
+            },
+            _ => Err(Error::Custom(String::from("Invalid or unsupported RsTx payload")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let versioned = RsTxPayload::new(7, [3u8; 12], vec![1u8; 36]);
+        assert!(versioned.encode().len() == 50);
+        assert!(versioned.encode()[0] == VERSION_RANDOM_NONCE);
+        assert!(RsTxPayload::decode(&versioned.encode()).unwrap() == versioned);
+
+        // legacy payloads, with and without a view tag
+        let tagged = RsTxPayload { version: VERSION_LEGACY, view_tag: Some(7), nonce: None, ciphertext: vec![1u8; 36] };
+        let untagged = RsTxPayload { version: VERSION_LEGACY, view_tag: None, nonce: None, ciphertext: vec![1u8; 36] };
+        assert!(RsTxPayload::decode(&tagged.encode()).unwrap() == tagged);
+        assert!(RsTxPayload::decode(&untagged.encode()).unwrap() == untagged);
+        assert!(untagged.encryption_nonce() == LEGACY_NONCE);
+        assert!(versioned.encryption_nonce() == [3u8; 12]);
+
+        let mut unknown_version = versioned.encode();
+        unknown_version[0] = 2;
+        assert!(RsTxPayload::decode(&unknown_version).is_err());
+        assert!(RsTxPayload::decode(&[0u8; 20]).is_err());
+    }
+}
@@ -0,0 +1,465 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+
+use rusqlite::{params, Connection, Row};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Error;
+use crate::utils::unix_timestamp;
+
+static PAYMENTS_DB: &'static str = "payments.db";
+
+/// Status of an incoming payment, or of an outgoing transfer
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStatus {
+    /// Announcement matched, no balance seen on the stealth address yet
+    Detected,
+    /// Announcement matched and the stealth address funded
+    Confirmed,
+    /// Balance swept to the destination of the sweep policy
+    Swept,
+    /// Outgoing transfer sent
+    Sent,
+    /// Announcement dropped by a chain reorganisation
+    Orphaned,
+}
+
+/// Incoming payment detected by the scanner
+#[derive(Clone, Debug)]
+pub struct Payment {
+    pub master_address: String,
+    pub stealth_address: String,
+    /// In wei, when known
+    pub amount: Option<String>,
+    pub announcement_tx: String,
+    pub funding_tx: Option<String>,
+    pub block: u64,
+    pub status: PaymentStatus,
+}
+
+/// Transfer sent with `transfer`
+pub struct OutgoingTransfer {
+    pub from_address: String,
+    /// Meta-address or public key of the recipient
+    pub recipient: String,
+    pub stealth_address: String,
+    pub amount: String,
+    pub funding_tx: String,
+    pub announcement_tx: String,
+    pub scheme: String,
+}
+
+#[derive(Default, Debug)]
+pub struct HistoryFilter {
+    /// Master key of payments, sender of transfers
+    pub key: Option<String>,
+    pub status: Option<PaymentStatus>,
+    /// Unix timestamps, `until` excluded
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+/// Output format of the history
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HistoryFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Incoming payment or outgoing transfer
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    /// UTC date of the detection or of the transfer
+    pub date: String,
+    /// `in` or `out`
+    pub direction: String,
+    /// Master key of payments, sender of transfers
+    pub key: String,
+    pub stealth_address: String,
+    pub amount: Option<String>,
+    pub announcement_tx: String,
+    pub funding_tx: Option<String>,
+    pub sweep_tx: Option<String>,
+    pub block: Option<u64>,
+    pub status: PaymentStatus,
+    #[serde(skip)]
+    timestamp: i64,
+}
+
+impl fmt::Display for PaymentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentStatus::Detected => write!(f, "detected"),
+            PaymentStatus::Confirmed => write!(f, "confirmed"),
+            PaymentStatus::Swept => write!(f, "swept"),
+            PaymentStatus::Sent => write!(f, "sent"),
+            PaymentStatus::Orphaned => write!(f, "orphaned")
+        }
+    }
+}
+
+impl FromStr for PaymentStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "detected" => Ok(PaymentStatus::Detected),
+            "confirmed" => Ok(PaymentStatus::Confirmed),
+            "swept" => Ok(PaymentStatus::Swept),
+            "sent" => Ok(PaymentStatus::Sent),
+            "orphaned" => Ok(PaymentStatus::Orphaned),
+            _ => Err(Error::Custom(format!("Unknown payment status: {}", s)))
+        }
+    }
+}
+
+impl FromStr for HistoryFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(HistoryFormat::Table),
+            "json" => Ok(HistoryFormat::Json),
+            "csv" => Ok(HistoryFormat::Csv),
+            _ => Err(Error::Custom(format!("Unknown output format: {}", s)))
+        }
+    }
+}
+
+fn open(storage_dir: &PathBuf) -> Result<Connection, Error> {
+    let mut path = storage_dir.clone();
+    path.push(PAYMENTS_DB);
+
+    let connection = Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS payments (
+            master_address TEXT NOT NULL,
+            stealth_address TEXT NOT NULL,
+            amount TEXT,
+            announcement_tx TEXT NOT NULL,
+            funding_tx TEXT,
+            block INTEGER NOT NULL,
+            status TEXT NOT NULL,
+            sweep_tx TEXT,
+            detected_at INTEGER NOT NULL,
+            PRIMARY KEY (announcement_tx, stealth_address)
+        );
+        CREATE TABLE IF NOT EXISTS transfers (
+            from_address TEXT NOT NULL,
+            recipient TEXT NOT NULL,
+            stealth_address TEXT NOT NULL,
+            amount TEXT NOT NULL,
+            funding_tx TEXT PRIMARY KEY,
+            announcement_tx TEXT NOT NULL,
+            scheme TEXT NOT NULL,
+            sent_at INTEGER NOT NULL
+        );"
+    )?;
+
+    Ok(connection)
+}
+
+/// Records a detected payment, a payment recorded again (after a restart,
+/// a reorg or once funded) only updates a detected or orphaned one
+pub fn record_payment(storage_dir: &PathBuf, payment: &Payment) -> Result<(), Error> {
+    let connection = open(&storage_dir)?;
+    connection.execute(
+        "INSERT INTO payments (master_address, stealth_address, amount, announcement_tx, funding_tx, block, status, detected_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ON CONFLICT (announcement_tx, stealth_address) DO UPDATE
+        SET amount = excluded.amount, funding_tx = COALESCE(excluded.funding_tx, payments.funding_tx),
+        block = excluded.block, status = excluded.status
+        WHERE payments.status IN ('detected', 'orphaned')",
+        params![
+            payment.master_address.to_lowercase(),
+            payment.stealth_address,
+            payment.amount,
+            payment.announcement_tx,
+            payment.funding_tx,
+            payment.block as i64,
+            payment.status.to_string(),
+            unix_timestamp() as i64
+        ]
+    )?;
+
+    Ok(())
+}
+
+/// Payments of the master key whose stealth address was not funded yet
+pub fn detected(storage_dir: &PathBuf, master_address: &str) -> Result<Vec<Payment>, Error> {
+    let connection = open(&storage_dir)?;
+    let mut statement = connection.prepare(
+        "SELECT master_address, stealth_address, amount, announcement_tx, funding_tx, block, status
+        FROM payments
+        WHERE master_address = ?1 AND status = ?2"
+    )?;
+    let rows = statement.query_map(
+        params![master_address.to_lowercase(), PaymentStatus::Detected.to_string()],
+        payment
+    )?;
+
+    let mut payments = vec![];
+    for row in rows {
+        payments.push(row?);
+    }
+
+    Ok(payments)
+}
+
+/// Funded payments of the master key whose funding tx is not known
+pub fn without_funding_tx(storage_dir: &PathBuf, master_address: &str) -> Result<Vec<Payment>, Error> {
+    let connection = open(&storage_dir)?;
+    let mut statement = connection.prepare(
+        "SELECT master_address, stealth_address, amount, announcement_tx, funding_tx, block, status
+        FROM payments
+        WHERE master_address = ?1 AND status IN ('confirmed', 'swept') AND funding_tx IS NULL"
+    )?;
+    let rows = statement.query_map(params![master_address.to_lowercase()], payment)?;
+
+    let mut payments = vec![];
+    for row in rows {
+        payments.push(row?);
+    }
+
+    Ok(payments)
+}
+
+pub fn set_funding_tx(storage_dir: &PathBuf, payment: &Payment, funding_tx: &str) -> Result<(), Error> {
+    let connection = open(&storage_dir)?;
+    connection.execute(
+        "UPDATE payments SET funding_tx = ?1 WHERE announcement_tx = ?2 AND stealth_address = ?3",
+        params![funding_tx, payment.announcement_tx, payment.stealth_address]
+    )?;
+
+    Ok(())
+}
+
+/// Marks the payments of the master key announced after the
+/// block as orphaned, until the re-scan records them again
+pub fn mark_orphaned(storage_dir: &PathBuf, master_address: &str, block: u64) -> Result<usize, Error> {
+    let connection = open(&storage_dir)?;
+    let orphaned = connection.execute(
+        "UPDATE payments SET status = ?1
+        WHERE master_address = ?2 AND block > ?3 AND status IN ('detected', 'confirmed')",
+        params![PaymentStatus::Orphaned.to_string(), master_address.to_lowercase(), block as i64]
+    )?;
+
+    Ok(orphaned)
+}
+
+pub fn mark_swept(storage_dir: &PathBuf, stealth_address: &str, sweep_tx: &str) -> Result<(), Error> {
+    let connection = open(&storage_dir)?;
+    connection.execute(
+        "UPDATE payments SET status = ?1, sweep_tx = ?2 WHERE stealth_address = ?3",
+        params![PaymentStatus::Swept.to_string(), sweep_tx, stealth_address]
+    )?;
+
+    Ok(())
+}
+
+pub fn record_transfer(storage_dir: &PathBuf, transfer: &OutgoingTransfer) -> Result<(), Error> {
+    let connection = open(&storage_dir)?;
+    connection.execute(
+        "INSERT OR REPLACE INTO transfers (from_address, recipient, stealth_address, amount, funding_tx, announcement_tx, scheme, sent_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            transfer.from_address.to_lowercase(),
+            transfer.recipient,
+            transfer.stealth_address,
+            transfer.amount,
+            transfer.funding_tx,
+            transfer.announcement_tx,
+            transfer.scheme,
+            unix_timestamp() as i64
+        ]
+    )?;
+
+    Ok(())
+}
+
+/// Payments and transfers matching the filter, oldest first
+pub fn history(storage_dir: &PathBuf, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>, Error> {
+    let connection = open(&storage_dir)?;
+    let key = filter.key.as_ref().map(|key| key.to_lowercase());
+    let status = filter.status.map(|status| status.to_string());
+    let mut entries = vec![];
+
+    if filter.status != Some(PaymentStatus::Sent) {
+        let mut statement = connection.prepare(
+            "SELECT master_address, stealth_address, amount, announcement_tx, funding_tx, block, status, sweep_tx, detected_at
+            FROM payments
+            WHERE (?1 IS NULL OR master_address = ?1) AND (?2 IS NULL OR status = ?2)
+            AND (?3 IS NULL OR detected_at >= ?3) AND (?4 IS NULL OR detected_at < ?4)"
+        )?;
+        let rows = statement.query_map(params![key, status, filter.since, filter.until], payment_entry)?;
+        for row in rows {
+            entries.push(row?);
+        }
+    }
+
+    if filter.status.is_none() || filter.status == Some(PaymentStatus::Sent) {
+        let mut statement = connection.prepare(
+            "SELECT from_address, stealth_address, amount, announcement_tx, funding_tx, sent_at
+            FROM transfers
+            WHERE (?1 IS NULL OR from_address = ?1)
+            AND (?2 IS NULL OR sent_at >= ?2) AND (?3 IS NULL OR sent_at < ?3)"
+        )?;
+        let rows = statement.query_map(params![key, filter.since, filter.until], transfer_entry)?;
+        for row in rows {
+            entries.push(row?);
+        }
+    }
+
+    entries.sort_by_key(|entry| entry.timestamp);
+
+    Ok(entries)
+}
+
+fn payment(row: &Row) -> rusqlite::Result<Payment> {
+    let status: String = row.get(6)?;
+    let block: i64 = row.get(5)?;
+
+    Ok(Payment {
+        master_address: row.get(0)?,
+        stealth_address: row.get(1)?,
+        amount: row.get(2)?,
+        announcement_tx: row.get(3)?,
+        funding_tx: row.get(4)?,
+        block: block as u64,
+        status: status.parse().unwrap_or(PaymentStatus::Detected)
+    })
+}
+
+fn payment_entry(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    let status: String = row.get(6)?;
+    let block: i64 = row.get(5)?;
+    let timestamp: i64 = row.get(8)?;
+
+    Ok(HistoryEntry {
+        date: format_date(timestamp),
+        direction: String::from("in"),
+        key: row.get(0)?,
+        stealth_address: row.get(1)?,
+        amount: row.get(2)?,
+        announcement_tx: row.get(3)?,
+        funding_tx: row.get(4)?,
+        sweep_tx: row.get(7)?,
+        block: Some(block as u64),
+        status: status.parse().unwrap_or(PaymentStatus::Detected),
+        timestamp: timestamp
+    })
+}
+
+fn transfer_entry(row: &Row) -> rusqlite::Result<HistoryEntry> {
+    let timestamp: i64 = row.get(5)?;
+
+    Ok(HistoryEntry {
+        date: format_date(timestamp),
+        direction: String::from("out"),
+        key: row.get(0)?,
+        stealth_address: row.get(1)?,
+        amount: Some(row.get(2)?),
+        announcement_tx: row.get(3)?,
+        funding_tx: Some(row.get(4)?),
+        sweep_tx: None,
+        block: None,
+        status: PaymentStatus::Sent,
+        timestamp: timestamp
+    })
+}
+
+fn format_date(timestamp: i64) -> String {
+    NaiveDateTime::from_timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_history() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let mut payment = Payment {
+            master_address: String::from("0xAA"),
+            stealth_address: String::from("0x01"),
+            amount: None,
+            announcement_tx: String::from("0xabcd"),
+            funding_tx: None,
+            block: 42,
+            status: PaymentStatus::Detected
+        };
+        record_payment(&storage_dir, &payment).unwrap();
+        payment.amount = Some(String::from("1000"));
+        payment.status = PaymentStatus::Confirmed;
+        record_payment(&storage_dir, &payment).unwrap();
+        assert!(history(&storage_dir, &HistoryFilter::default()).unwrap().len() == 1);
+
+        record_transfer(&storage_dir, &OutgoingTransfer {
+            from_address: String::from("0xBB"),
+            recipient: String::from("st:eth:0x02"),
+            stealth_address: String::from("0x03"),
+            amount: String::from("500"),
+            funding_tx: String::from("0x1111"),
+            announcement_tx: String::from("0x2222"),
+            scheme: String::from("eip5564")
+        }).unwrap();
+
+        let all = history(&storage_dir, &HistoryFilter::default()).unwrap();
+        assert!(all.len() == 2);
+
+        let filter = HistoryFilter { key: Some(String::from("0xaa")), ..Default::default() };
+        let entries = history(&storage_dir, &filter).unwrap();
+        assert!(entries.len() == 1 && entries[0].status == PaymentStatus::Confirmed);
+        assert!(entries[0].amount == Some(String::from("1000")));
+
+        mark_swept(&storage_dir, "0x01", "0x3333").unwrap();
+        let filter = HistoryFilter { status: Some(PaymentStatus::Swept), ..Default::default() };
+        let entries = history(&storage_dir, &filter).unwrap();
+        assert!(entries.len() == 1 && entries[0].sweep_tx == Some(String::from("0x3333")));
+
+        let filter = HistoryFilter { status: Some(PaymentStatus::Sent), ..Default::default() };
+        assert!(history(&storage_dir, &filter).unwrap()[0].direction == "out");
+
+        // detected payments are confirmed along with their funding tx
+        let mut unfunded = Payment {
+            stealth_address: String::from("0x04"),
+            announcement_tx: String::from("0xef01"),
+            amount: None,
+            block: 50,
+            status: PaymentStatus::Detected,
+            ..payment
+        };
+        record_payment(&storage_dir, &unfunded).unwrap();
+        assert!(detected(&storage_dir, "0xaa").unwrap().len() == 1);
+        unfunded.amount = Some(String::from("2000"));
+        unfunded.funding_tx = Some(String::from("0x4444"));
+        unfunded.status = PaymentStatus::Confirmed;
+        record_payment(&storage_dir, &unfunded).unwrap();
+        assert!(detected(&storage_dir, "0xaa").unwrap().is_empty());
+
+        // the funding tx of a confirmed payment is filled in later
+        let unresolved = without_funding_tx(&storage_dir, "0xaa").unwrap();
+        assert!(unresolved.len() == 1 && unresolved[0].stealth_address == "0x01");
+        set_funding_tx(&storage_dir, &unresolved[0], "0x5555").unwrap();
+        assert!(without_funding_tx(&storage_dir, "0xaa").unwrap().is_empty());
+
+        // a reorg orphans the payments after the fork, until re-included
+        assert!(mark_orphaned(&storage_dir, "0xAA", 45).unwrap() == 1);
+        let filter = HistoryFilter { status: Some(PaymentStatus::Orphaned), ..Default::default() };
+        let entries = history(&storage_dir, &filter).unwrap();
+        assert!(entries.len() == 1 && entries[0].funding_tx == Some(String::from("0x4444")));
+        unfunded.block = 51;
+        record_payment(&storage_dir, &unfunded).unwrap();
+        assert!(history(&storage_dir, &filter).unwrap().is_empty());
+    }
+}
@@ -1,8 +1,26 @@
+use std::cmp;
+
 use graphql_client::{GraphQLQuery, Response};
 
-use web3::types::U64;
+use hex::FromHex;
+
+use web3::Web3;
+use web3::futures::Future;
+use web3::transports::Http;
+use web3::types::{BlockId, BlockNumber, FilterBuilder, H160, H256, Log, U64};
+
+use crate::client::Client;
+use crate::errors::{Error, QueryError};
+use crate::scheme::StealthScheme;
+use crate::transfer::{ERC5564_ANNOUNCER_ADDRESS, RS_TX_CONTRACT_ADDRESS};
+
+static SUBGRAPH_URL: &'static str = "http://127.0.0.1:8000/subgraphs/name/roynalnaruto/rs_tx_subgraph";
+
+// graph nodes return at most 1000 entities per query
+const SUBGRAPH_PAGE_SIZE: i64 = 1000;
 
-use crate::errors::Error;
+// block range of a single eth_getLogs request
+const LOGS_CHUNK_SIZE: u64 = 5000;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -18,56 +36,76 @@ pub struct Transactions;
 pub struct RsTxTransaction {
     pub id: String,
     pub block: U64,
+    pub scheme: StealthScheme,
     pub nonce_point: String,
+    /// Encrypted recipient (RsTx scheme only)
     pub encrypted_recipient: Vec<u8>,
+    /// Announced stealth address (EIP-5564 scheme only)
+    pub stealth_address: Option<H160>,
+    /// Announcement metadata (EIP-5564 scheme only)
+    pub metadata: Vec<u8>,
 }
 
-pub fn query(block_number: i64) -> Vec<RsTxTransaction> {
-    let query_vars = transactions::Variables {
-        block_gt: block_number
-    };
+/// RsTx transactions in the block range `(block_gt, block_lte]`, ordered by block
+pub fn query(client: &Client, block_gt: i64, block_lte: i64) -> Result<Vec<RsTxTransaction>, Error> {
+    // page through the block range by id, a short
+    // page means that the range is exhausted
+    let mut rs_tx_txs: Vec<RsTxTransaction> = vec![];
+    let mut id_gt = String::new();
+    loop {
+        let query_vars = transactions::Variables {
+            block_gt: block_gt,
+            block_lte: block_lte,
+            id_gt: id_gt.clone(),
+            first: SUBGRAPH_PAGE_SIZE
+        };
+        let page = query_transactions(&client, query_vars)?;
+        if let Some(last_tx) = page.last() {
+            id_gt = last_tx.id.clone();
+        }
+        // like a failed page, a record that does not decode fails
+        // the range rather than being skipped without a trace
+        let page_txs = page
+            .iter()
+            .map(|tx| convert_fields(&tx))
+            .collect::<Result<Vec<RsTxTransaction>, Error>>()?;
+        rs_tx_txs.extend(page_txs);
 
-    match query_transactions(query_vars) {
-        Ok(rs_tx_txs) => rs_tx_txs,
-        Err(_) => vec![]
+        if page.len() < SUBGRAPH_PAGE_SIZE as usize {
+            break;
+        }
     }
+    rs_tx_txs.sort_by_key(|tx| tx.block);
+
+    Ok(rs_tx_txs)
 }
 
-fn query_transactions(variables: transactions::Variables) -> Result<Vec<RsTxTransaction>, anyhow::Error> {
+fn query_transactions(client: &Client, variables: transactions::Variables) -> Result<Vec<transactions::TransactionsTransactions>, Error> {
     let request_body = Transactions::build_query(variables);
 
-    let client = reqwest::Client::new();
     let mut res = client
-        .post("http://127.0.0.1:8000/subgraphs/name/roynalnaruto/rs_tx_subgraph")
+        .http
+        .post(SUBGRAPH_URL)
         .json(&request_body)
-        .send()?;
-
-    let response_body: Response<transactions::ResponseData> = res.json()?;
-
-    let txs = match response_body.errors {
-        Some(errors) => {
-            eprintln!("[query] ERRORS:");
-            for error in errors {
-                eprintln!("{:?}", error);
-            }
-
-            vec![]
-        },
-        None => {
-            let response_data: transactions::ResponseData = response_body.data.expect("[query] Missing response data");
-            let rs_tx_transactions = response_data
-                .transactions
-                .expect("[query] No transactions found")
-                .iter()
-                .map(|tx| convert_fields(&tx))
-                .filter_map(Result::ok)
-                .collect();
-
-            rs_tx_transactions
-        }
-    };
+        .send()
+        .map_err(QueryError::Subgraph)?;
+
+    let response_body: Response<transactions::ResponseData> = res.json().map_err(QueryError::Subgraph)?;
+
+    // a failed page fails the whole page set, so
+    // that no transaction of the range is skipped
+    if let Some(errors) = response_body.errors {
+        return Err(Error::Query(QueryError::GraphQL(errors)));
+    }
+
+    let response_data: transactions::ResponseData = response_body
+        .data
+        .ok_or(QueryError::MissingData("response data"))?;
+    let transactions = response_data
+        .transactions
+        .ok_or(QueryError::MissingData("transactions"))?;
 
-    Ok(txs)
+    Ok(transactions)
 }
 
 fn convert_fields(tx: &transactions::TransactionsTransactions) -> Result<RsTxTransaction, Error> {
@@ -78,8 +116,182 @@ fn convert_fields(tx: &transactions::TransactionsTransactions) -> Result<RsTxTra
     let rs_tx = RsTxTransaction {
         id: tx.id.clone(),
         block: block,
+        scheme: StealthScheme::RsTx,
         nonce_point: String::from(nonce_point_str),
-        encrypted_recipient: encrypted_recipient
+        encrypted_recipient: encrypted_recipient,
+        stealth_address: None,
+        metadata: vec![]
+    };
+
+    Ok(rs_tx)
+}
+
+/// Latest block indexed by the subgraph
+pub fn subgraph_block(client: &Client) -> Result<u64, Error> {
+    let request_body = serde_json::json!({ "query": "{ _meta { block { number } } }" });
+
+    let mut res = client
+        .http
+        .post(SUBGRAPH_URL)
+        .json(&request_body)
+        .send()
+        .map_err(QueryError::Subgraph)?;
+
+    let response_body: serde_json::Value = res.json().map_err(QueryError::Subgraph)?;
+    response_body["data"]["_meta"]["block"]["number"]
+        .as_u64()
+        .ok_or(Error::Query(QueryError::MissingData("subgraph block")))
+}
+
+pub fn latest_block(client: &Client) -> Result<u64, Error> {
+    let latest_block = client.web3.eth().block_number().wait().map_err(QueryError::Rpc)?;
+
+    Ok(latest_block.as_u64())
+}
+
+pub fn block_hash(client: &Client, block_number: u64) -> Result<Option<H256>, Error> {
+    let block = client.web3.eth()
+        .block(BlockId::Number(BlockNumber::Number(U64::from(block_number))))
+        .wait()
+        .map_err(QueryError::Rpc)?;
+
+    Ok(block.and_then(|block| block.hash))
+}
+
+/// EIP-5564 announcements in the block range `(block_gt, block_lte]`
+pub fn query_announcements(client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+    // form contract abi
+    let json_abi: &[u8] = include_bytes!("contracts/ERC5564Announcer.abi");
+    let abi = ethabi::Contract::load(json_abi)?;
+    let event = abi.event("Announcement")?;
+
+    // filter Announcement events of the secp256k1 scheme
+    let contract_address = <[u8; 20]>::from_hex(ERC5564_ANNOUNCER_ADDRESS)?;
+    let mut scheme_id = [0u8; 32];
+    scheme_id[31] = StealthScheme::Eip5564.id().unwrap_or(1) as u8;
+    let logs = fetch_logs(&client.web3, H160::from(contract_address), &event, Some(H256::from(scheme_id)), block_gt + 1, block_lte)?;
+
+    let announcements = logs
+        .iter()
+        .map(|log| convert_announcement(&event, &log))
+        .collect::<Result<Vec<RsTxTransaction>, Error>>()?;
+
+    Ok(announcements)
+}
+
+/// NewRsTx logs in the block range `(block_gt, block_lte]`
+pub fn query_logs(client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+    // form contract abi
+    let json_abi: &[u8] = include_bytes!("contracts/RsTx.abi");
+    let abi = ethabi::Contract::load(json_abi)?;
+    let event = abi.event("NewRsTx")?;
+
+    // same transactions as the subgraph would
+    // return, read directly from the node
+    let contract_address = <[u8; 20]>::from_hex(RS_TX_CONTRACT_ADDRESS)?;
+    let logs = fetch_logs(&client.web3, H160::from(contract_address), &event, None, block_gt + 1, block_lte)?;
+
+    let rs_tx_txs = logs
+        .iter()
+        .map(|log| convert_rs_tx_log(&event, &log))
+        .collect::<Result<Vec<RsTxTransaction>, Error>>()?;
+
+    Ok(rs_tx_txs)
+}
+
+fn fetch_logs(
+    web3: &Web3<Http>,
+    contract_address: H160,
+    event: &ethabi::Event,
+    topic1: Option<H256>,
+    from_block: u64,
+    to_block: u64
+) -> Result<Vec<Log>, Error> {
+    let signature = H256::from_slice(event.signature().as_bytes());
+
+    // nodes limit the block range (or result size) of
+    // eth_getLogs, so the range is requested in chunks
+    let mut logs = vec![];
+    let mut chunk_start = from_block;
+    while chunk_start <= to_block {
+        let chunk_end = cmp::min(chunk_start + LOGS_CHUNK_SIZE - 1, to_block);
+        let filter = FilterBuilder::default()
+            .address(vec![contract_address])
+            .topics(Some(vec![signature]), topic1.map(|topic| vec![topic]), None, None)
+            .from_block(BlockNumber::Number(U64::from(chunk_start)))
+            .to_block(BlockNumber::Number(U64::from(chunk_end)))
+            .build();
+        logs.extend(web3.eth().logs(filter).wait().map_err(QueryError::Rpc)?);
+        chunk_start = chunk_end + 1;
+    }
+
+    Ok(logs)
+}
+
+fn parse_log(event: &ethabi::Event, log: &Log) -> Result<ethabi::Log, Error> {
+    let raw_log = ethabi::RawLog {
+        topics: log.topics.iter().map(|topic| ethabi::Hash::from_slice(topic.as_bytes())).collect(),
+        data: log.data.0.clone()
+    };
+    let parsed_log = event.parse_log(raw_log)?;
+
+    Ok(parsed_log)
+}
+
+fn convert_rs_tx_log(event: &ethabi::Event, log: &Log) -> Result<RsTxTransaction, Error> {
+    let parsed_log = parse_log(&event, &log)?;
+
+    let mut nonce_point = vec![];
+    let mut encrypted_recipient = vec![];
+    for param in parsed_log.params {
+        match (param.name.as_str(), param.value) {
+            ("noncePoint", ethabi::Token::Bytes(bytes)) => nonce_point = bytes,
+            ("encryptedRecipient", ethabi::Token::Bytes(bytes)) => encrypted_recipient = bytes,
+            _ => {}
+        }
+    }
+
+    let tx_hash = log.transaction_hash.ok_or_else(|| Error::Custom(String::from("Pending NewRsTx log")))?;
+    let block = log.block_number.ok_or_else(|| Error::Custom(String::from("Pending NewRsTx log")))?;
+    let rs_tx = RsTxTransaction {
+        id: format!("{:?}", tx_hash),
+        block: block,
+        scheme: StealthScheme::RsTx,
+        nonce_point: hex::encode(nonce_point),
+        encrypted_recipient: encrypted_recipient,
+        stealth_address: None,
+        metadata: vec![]
+    };
+
+    Ok(rs_tx)
+}
+
+fn convert_announcement(event: &ethabi::Event, log: &Log) -> Result<RsTxTransaction, Error> {
+    let parsed_log = parse_log(&event, &log)?;
+
+    // pick the announced fields by name
+    let mut stealth_address = None;
+    let mut ephemeral_public_key = vec![];
+    let mut metadata = vec![];
+    for param in parsed_log.params {
+        match (param.name.as_str(), param.value) {
+            ("stealthAddress", ethabi::Token::Address(address)) => stealth_address = Some(H160::from_slice(address.as_bytes())),
+            ("ephemeralPubKey", ethabi::Token::Bytes(bytes)) => ephemeral_public_key = bytes,
+            ("metadata", ethabi::Token::Bytes(bytes)) => metadata = bytes,
+            _ => {}
+        }
+    }
+
+    let tx_hash = log.transaction_hash.ok_or_else(|| Error::Custom(String::from("Pending announcement log")))?;
+    let block = log.block_number.ok_or_else(|| Error::Custom(String::from("Pending announcement log")))?;
+    let rs_tx = RsTxTransaction {
+        id: format!("{:?}", tx_hash),
+        block: block,
+        scheme: StealthScheme::Eip5564,
+        nonce_point: hex::encode(ephemeral_public_key),
+        encrypted_recipient: vec![],
+        stealth_address: stealth_address,
+        metadata: metadata
     };
 
     Ok(rs_tx)
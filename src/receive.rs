@@ -1,15 +1,17 @@
 use std::path::PathBuf;
 use std::str::FromStr;
 
-use parity_crypto::Keccak256;
-use parity_crypto::publickey::ec_math_utils;
-use parity_crypto::publickey::{KeyPair, Secret};
+use parity_crypto::publickey::{KeyPair, Public, Secret};
+use parity_crypto::publickey::public_to_address;
 
 use web3::futures::Future;
 use web3::types::{H160, U256};
 
 use crate::errors::Error;
+use crate::index;
+use crate::index::Announcement;
 use crate::key;
+use crate::scheme::StealthScheme;
 use crate::utils::convert_h160;
 
 pub struct Receipt {
@@ -20,39 +22,87 @@ pub struct Receipt {
 pub fn receive(
     master_path: &PathBuf,
     master_address: &str,
-    nonce_point_str: &str
+    passphrase: &str,
+    nonce_point_str: &str,
+    scheme: StealthScheme,
+    announcement: Option<&Announcement>
 ) -> Result<Receipt, Error> {
     // instantiate web3
     let (_eloop, transport) = web3::transports::Http::new("http://127.0.0.1:8545").unwrap();
     let web3 = web3::Web3::new(transport);
 
-    // load master keypair
+    let address = derive(&master_path, &master_address, &passphrase, &nonce_point_str, scheme, announcement)?;
+
+    // query balance and form receipt
+    let balance = web3.eth().balance(address, None).wait().unwrap();
+    let receipt = Receipt {
+        address: address,
+        balance: balance
+    };
+
+    Ok(receipt)
+}
+
+/// Derives and stores the stealth key of a
+/// payment, returns its address without any RPC
+pub fn derive(
+    master_path: &PathBuf,
+    master_address: &str,
+    passphrase: &str,
+    nonce_point_str: &str,
+    scheme: StealthScheme,
+    announcement: Option<&Announcement>
+) -> Result<H160, Error> {
+    // load master spend and scan keypairs
     let mut keys_path = master_path.clone();
     let mut copy_keys_path = master_path.clone();
-    let master_keypair = key::load(&mut copy_keys_path, &master_address)?;
+    let master_keypair = key::load(&mut copy_keys_path, &master_address, &passphrase)?;
+    let scan_keypair = key::load_scan(&master_path, &master_address, &passphrase)?;
     let master_secret_key = master_keypair.secret().clone();
 
-    // calculate the ecdh shared secret
+    // calculate the ecdh shared secret with the scan key
     let (nonce_point, _) = key::public_key_from_str(nonce_point_str)?;
-    let mut ecdh_shared_secret = nonce_point.clone();
-    ec_math_utils::public_mul_secret(&mut ecdh_shared_secret, &master_secret_key)?;
-    let ecdh_shared_secret_number = Secret::from_str(&hex::encode(ecdh_shared_secret.keccak256()))?;
+    let ecdh_shared_secret = scheme.shared_secret(&nonce_point, scan_keypair.secret())?;
+    let ecdh_shared_secret_number = Secret::from_str(&hex::encode(ecdh_shared_secret))?;
 
-    // calculate recipient secret key
+    // calculate recipient secret key, which
+    // requires the master spend secret key
     let mut recipient_secret_key = master_secret_key.clone();
     recipient_secret_key.add(&ecdh_shared_secret_number)?;
     let recipient_keypair = KeyPair::from_secret(recipient_secret_key)?;
 
     // store this key along with the master key
-    key::store(&mut keys_path, &recipient_keypair)?;
-
-    // query balance and form receipt
-    let address = convert_h160(recipient_keypair.address());
-    let balance = web3.eth().balance(address, None).wait().unwrap();
-    let receipt = Receipt {
-        address: address,
-        balance: balance
+    // encrypted with the same passphrase
+    let origin = key::StealthOrigin {
+        master_address: format!("{:?}", master_keypair.address()),
+        nonce_point: String::from(nonce_point_str)
     };
+    key::store_stealth(&mut keys_path, &recipient_keypair, &passphrase, &origin)?;
 
-    Ok(receipt)
+    // record its provenance in the wallet index
+    index::record(
+        &master_path,
+        &format!("{:?}", recipient_keypair.address()),
+        &origin.master_address,
+        &origin.nonce_point,
+        scheme,
+        announcement
+    )?;
+
+    Ok(convert_h160(recipient_keypair.address()))
+}
+
+/// Stealth address of a payment, from only
+/// the scan secret and the spend public key
+pub fn stealth_address(
+    spend_public_key: &Public,
+    scan_keypair: &KeyPair,
+    nonce_point_str: &str,
+    scheme: StealthScheme
+) -> Result<H160, Error> {
+    let (nonce_point, _) = key::public_key_from_str(nonce_point_str)?;
+    let ecdh_shared_secret = scheme.shared_secret(&nonce_point, scan_keypair.secret())?;
+    let recipient_public_key = key::stealth_public(&spend_public_key, &ecdh_shared_secret)?;
+
+    Ok(convert_h160(public_to_address(&recipient_public_key)))
 }
@@ -1,56 +1,151 @@
-use std::fs::File;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread;
 use std::time::Duration;
 use std::env;
 
 use aes_gcm::Aes256Gcm;
 use aead::{Aead, NewAead, generic_array::GenericArray};
 
-use job_scheduler::{JobScheduler, Job};
+use job_scheduler::{JobScheduler, Job, Schedule};
 
 use daemonize::Daemonize;
 
-use parity_crypto::Keccak256;
-use parity_crypto::publickey::ec_math_utils;
-use parity_crypto::publickey::KeyPair;
+use parity_crypto::publickey::{Address, KeyPair, Public};
+use parity_crypto::publickey::public_to_address;
 
-use web3::futures::Future;
-use web3::types::{H256, TransactionId::Hash, U64};
+use web3::types::{H160, H256};
 
+use crate::checkpoint;
+use crate::checkpoint::{Checkpoint, CheckpointLock, PendingMatch};
+use crate::client;
+use crate::client::Client;
+use crate::config;
+use crate::config::ScanConfig;
+use crate::daemon;
+use crate::daemon::{DaemonPaths, ScanStatus};
 use crate::errors::Error;
+use crate::health::Health;
+use crate::index;
 use crate::key;
-use crate::query;
+use crate::list;
+use crate::notify;
+use crate::notify::{NotificationSink, PaymentNotification};
+use crate::payload::RsTxPayload;
+use crate::payments;
+use crate::payments::{Payment, PaymentStatus};
 use crate::query::RsTxTransaction;
 use crate::receive;
-use crate::utils::{convert_u64_i64, convert_str_h256};
+use crate::scheme::StealthScheme;
+use crate::source::AnnouncementSource;
+use crate::sweep;
+use crate::utils::{convert_h160, unix_timestamp};
+
+/// Scan state of a master key
+pub struct ScanTarget {
+    pub master_address: String,
+    pub scan_keypair: KeyPair,
+    pub spend_public_key: Public,
+    pub checkpoint: Checkpoint,
+}
+
+// longest wait before a stop signal is noticed
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// funding txs looked up after each batch, so that a
+// backlog of old payments is filled in over a few batches
+const FUNDING_TXS_PER_BATCH: usize = 16;
+
+/// What starts a scan batch
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    /// Every tick of the cron schedule
+    Schedule,
+    /// Every new chain head, pushed by the node over WebSocket
+    NewHeads,
+}
+
+impl FromStr for Trigger {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schedule" => Ok(Trigger::Schedule),
+            "new-heads" => Ok(Trigger::NewHeads),
+            _ => Err(Error::Custom(format!("Unknown scan trigger: {}", s)))
+        }
+    }
+}
+
+/// Options of the `scan start` command
+pub struct ScanOptions {
+    pub master_addresses: Vec<String>,
+    /// Scan for every master key in the storage dir
+    pub all: bool,
+    pub from_block: Option<u64>,
+    pub reset: bool,
+    pub confirmations: u64,
+    pub error_budget: u32,
+    pub config_file: Option<PathBuf>,
+    /// Run in the foreground instead of daemonizing
+    pub foreground: bool,
+    pub trigger: Trigger,
+    /// Cron expression (with seconds) of the schedule trigger
+    pub schedule: String,
+    /// WebSocket endpoint of the node, for the new heads trigger
+    pub ws_url: String,
+}
 
 pub fn scan(
     storage_dir: &PathBuf,
-    master_address: &str,
-    from_block: Option<u64>
+    passphrase: &str,
+    options: &ScanOptions,
+    paths: &DaemonPaths,
+    source: Box<dyn AnnouncementSource>
 ) -> Result<(), Error> {
-    // instantiate web3
-    let (_eloop, transport) = web3::transports::Http::new("http://127.0.0.1:8545").unwrap();
-    let web3 = web3::Web3::new(transport);
-    let latest_block = web3.eth().block_number().wait().unwrap();
-
-    let block_number = match from_block {
-        Some(b) => U64::from(b),
-        None => latest_block - U64::from(2)
-    };
+    // check the schedule, and load every key and the
+    // config before daemonizing, so that errors fail early
+    if options.trigger == Trigger::Schedule {
+        options.schedule
+            .parse::<Schedule>()
+            .map_err(|e| Error::Custom(format!("Invalid schedule {}: {:?}", options.schedule, e)))?;
+    }
+    let config = config::load(&options.config_file)?;
+    let confirmations = config.confirmations.unwrap_or(options.confirmations);
+    let mut locks = HashMap::new();
+    let targets = load_targets(
+        &storage_dir,
+        &passphrase,
+        &options,
+        options.from_block,
+        options.reset,
+        confirmations,
+        source.as_ref(),
+        &mut locks
+    )?;
+
+    // under systemd or in a container, the process
+    // stays attached and logs to stdout and stderr
+    if options.foreground {
+        return _scan(&storage_dir, &passphrase, &options, targets, config, locks, &paths, source.as_ref());
+    }
 
     // create log files
-    let cwd = env::current_dir()?;
-    let mut stdout_path = cwd.clone();
-    stdout_path.push(".logs/scan.out");
-    let mut stderr_path = cwd.clone();
-    stderr_path.push(".logs/scan.err");
-    let stdout = File::create(stdout_path).unwrap();
-    let stderr = File::create(stderr_path).unwrap();
-
-    // daemonize the scan process
+    fs::create_dir_all(&paths.log_dir)?;
+    let stdout = File::create(paths.stdout())?;
+    let stderr = File::create(paths.stderr())?;
+
+    // daemonize the scan process, staying in the current
+    // directory so that relative storage paths still resolve
     let daemonize = Daemonize::new()
-        .pid_file("/tmp/rs_tx_scan.pid")
+        .working_directory(env::current_dir()?)
+        .pid_file(&paths.pid_file)
         .chown_pid_file(true)
         .stdout(stdout)
         .stderr(stderr)
@@ -58,103 +153,617 @@ pub fn scan(
 
     match daemonize.start() {
         Ok(_) => {
-            let master_keypair = key::load(&storage_dir, &master_address)?;
-            _scan(&storage_dir, &master_keypair, block_number);
+            // the checkpoint locks are inherited by the daemon
+            let result = _scan(&storage_dir, &passphrase, &options, targets, config, locks, &paths, source.as_ref());
+            let _ = fs::remove_file(&paths.pid_file);
 
-            Ok(())
+            result
         },
         Err(e) => Err(Error::Daemonize(e))
     }
 }
 
+/// Loads the scan keys of the master keys to scan for, along with
+/// the checkpoints to resume scanning from, locking the new ones
+fn load_targets(
+    storage_dir: &PathBuf,
+    passphrase: &str,
+    options: &ScanOptions,
+    from_block: Option<u64>,
+    reset: bool,
+    confirmations: u64,
+    source: &dyn AnnouncementSource,
+    locks: &mut HashMap<String, CheckpointLock>
+) -> Result<Vec<ScanTarget>, Error> {
+    let master_addresses = if options.all {
+        list::master_addresses(&storage_dir)?
+    } else {
+        options.master_addresses.clone()
+    };
+    if master_addresses.is_empty() {
+        return Err(Error::Custom(String::from("No master key to scan for")));
+    }
+
+    let mut start_block = None;
+    let mut targets = vec![];
+    for master_address in master_addresses {
+        let master_address = master_address.to_lowercase();
+        if !locks.contains_key(&master_address) {
+            let checkpoint_lock = checkpoint::lock(&storage_dir, &master_address)?;
+            locks.insert(master_address.clone(), checkpoint_lock);
+        }
+
+        // resume from the stored checkpoint, unless it is reset
+        // or the start block is given explicitly
+        if reset {
+            checkpoint::reset(&storage_dir, &master_address)?;
+        }
+        let checkpoint = match checkpoint::resume(&storage_dir, &master_address, from_block)? {
+            Some(checkpoint) => checkpoint,
+            None => {
+                if start_block.is_none() {
+                    let client = Client::new()?;
+                    start_block = Some(source.start_block(&client, confirmations)?);
+                }
+                Checkpoint {
+                    block: start_block.unwrap_or(0),
+                    ..Default::default()
+                }
+            }
+        };
+
+        // only the scan keypair and the spend public key
+        let scan_keypair = key::load_scan(&storage_dir, &master_address, &passphrase)?;
+        let spend_public_key = key::load_public(&storage_dir, &master_address)?;
+        targets.push(ScanTarget {
+            master_address: master_address,
+            scan_keypair: scan_keypair,
+            spend_public_key: spend_public_key,
+            checkpoint: checkpoint
+        });
+    }
+
+    Ok(targets)
+}
+
 fn _scan(
     storage_dir: &PathBuf,
-    keypair: &KeyPair,
-    mut block_number: U64
-) {
-    let mut sched = JobScheduler::new();
-    sched.add(Job::new("1/10 * * * * *".parse().unwrap(), || {
-        match convert_u64_i64(block_number.as_u64()) {
-            Some(b) => {
-                let txs = query::query(b);
-
-                let my_txs: Vec<RsTxTransaction> = txs
-                    .iter()
-                    .filter_map(|tx| is_my_tx(tx, &keypair).ok())
-                    .collect();
-
-                for tx in my_txs {
-                    let own_address = format!("{:?}", keypair.address());
-                    match receive::receive(
-                        &storage_dir,
-                        &own_address,
-                        &tx.nonce_point.as_str()
-                    ) {
-                        Ok(receipt) => {
-                            println!("Successfully claimed receipt");
-                            println!("Recipient address: {:?}", receipt.address);
-                            println!("Recipient balance: {:?}", receipt.balance);
-                        },
-                        Err(e) => eprintln!("error = {:?}", e)
+    passphrase: &str,
+    options: &ScanOptions,
+    mut targets: Vec<ScanTarget>,
+    mut config: ScanConfig,
+    mut locks: HashMap<String, CheckpointLock>,
+    paths: &DaemonPaths,
+    source: &dyn AnnouncementSource
+) -> Result<(), Error> {
+    // a single client for the whole daemon, created after
+    // daemonizing as its event loop runs in a thread
+    let client = Client::new()?;
+    let mut health = Health::new(config.error_budget.unwrap_or(options.error_budget));
+
+    // SIGTERM and SIGINT stop the scanner after the current
+    // batch, SIGHUP reloads the keys and the config
+    let terminate = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::SIGTERM, terminate.clone())?;
+    signal_hook::flag::register(signal_hook::SIGINT, terminate.clone())?;
+    signal_hook::flag::register(signal_hook::SIGHUP, reload.clone())?;
+
+    // report the status to `scan status` over the socket, from
+    // here on errors stop the loop so that the socket is removed
+    let status = Arc::new(Mutex::new(ScanStatus::new()));
+    daemon::serve_status(&paths.socket, status.clone())?;
+    let mut failure = None;
+
+    // payments whose funding tx was looked up already, each
+    // of them is only tried once while the daemon runs
+    let mut funding_looked_up = HashSet::new();
+
+    let mut batch = || {
+        if reload.swap(false, Ordering::SeqCst) {
+            // new keys start at the confirmed head, the
+            // others resume from their stored checkpoint
+            let reloaded = config::load(&options.config_file).and_then(|new_config| {
+                let confirmations = new_config.confirmations.unwrap_or(options.confirmations);
+                let new_targets = load_targets(&storage_dir, &passphrase, &options, None, false, confirmations, source, &mut locks)?;
+                Ok((new_config, new_targets))
+            });
+            match reloaded {
+                Ok((new_config, new_targets)) => {
+                    health.set_error_budget(new_config.error_budget.unwrap_or(options.error_budget));
+                    config = new_config;
+                    targets = new_targets;
+                    println!("[scan] Reloaded {} master keys and the config", targets.len());
+                },
+                Err(e) => eprintln!("[scan] Error reloading, keeping the current keys and config: {:?}", e)
+            }
+        }
+
+        // a failing source is retried with exponential
+        // backoff, until the error budget is exhausted
+        if health.ready() {
+            let confirmations = config.confirmations.unwrap_or(options.confirmations);
+            match scan_once(&client, &storage_dir, &mut targets, confirmations, source) {
+                Ok(_) => {
+                    if health.record_success() {
+                        println!("[scan] Health: {}", health);
+                    }
+                },
+                Err(e) => {
+                    let recorded = health.record_failure(&e);
+                    eprintln!("[scan] Health: {}", health);
+                    if let Err(budget_error) = recorded {
+                        failure = Some(budget_error);
+                        terminate.store(true, Ordering::SeqCst);
                     }
                 }
+            }
+        }
+
+        // handle the committed matches, including the ones
+        // left over from before a crash or restart
+        for target in targets.iter_mut().filter(|target| !target.checkpoint.pending.is_empty()) {
+            handle_matches(&client, &storage_dir, &passphrase, target, &config);
+            if let Err(e) = checkpoint::store(&storage_dir, &target.master_address, &target.checkpoint) {
+                eprintln!("[scan] Error storing checkpoint: {:?}", e);
+            }
+        }
+
+        // payments announced before their funding
+        // are confirmed once it is mined
+        for target in targets.iter() {
+            if let Err(e) = confirm_detected(&client, &storage_dir, &target.master_address) {
+                eprintln!("[scan] Error confirming detected payments: {:?}", e);
+            }
+        }
+
+        // sweep the claimed payments that are due
+        for target in targets.iter_mut().filter(|target| !target.checkpoint.sweeps.is_empty()) {
+            let policy = match config.sweep_policy(&target.master_address) {
+                Some(policy) => policy,
+                None => continue
+            };
+            let remaining = sweep::sweep_due(&client, &storage_dir, &passphrase, &policy, target.checkpoint.sweeps.clone());
+            if remaining == target.checkpoint.sweeps {
+                continue;
+            }
+            target.checkpoint.sweeps = remaining;
+            if let Err(e) = checkpoint::store(&storage_dir, &target.master_address, &target.checkpoint) {
+                eprintln!("[scan] Error storing checkpoint: {:?}", e);
+            }
+        }
+
+        // fill in the funding txs last, they are
+        // only informative and may not be found
+        for target in targets.iter() {
+            fill_funding_txs(&client, &storage_dir, &target.master_address, &mut funding_looked_up);
+        }
 
-                // increment the block_number to
-                // the block number of the last tx
-                if let Some(last_tx) = txs.last() {
-                    block_number = last_tx.block;
+        if let Ok(mut status) = status.lock() {
+            status.update(&health, &targets);
+        }
+    };
+
+    // a batch always runs to completion, and leaves its checkpoints
+    // stored behind it, the stop signal is only checked between batches
+    let stopped_by = match options.trigger {
+        Trigger::Schedule => match options.schedule.parse::<Schedule>() {
+            Ok(schedule) => {
+                let mut sched = JobScheduler::new();
+                sched.add(Job::new(schedule, || batch()));
+                while !terminate.load(Ordering::SeqCst) {
+                    sched.tick();
+                    thread::sleep(cmp::min(sched.time_till_next_job(), SIGNAL_CHECK_INTERVAL));
                 }
+                None
             },
-            None => eprintln!("[scan] Error converting block number from u64 to i64")
+            Err(e) => Some(Error::Custom(format!("Invalid schedule {}: {:?}", options.schedule, e)))
+        },
+        Trigger::NewHeads => {
+            let heads = client::subscribe_new_heads(&options.ws_url);
+            loop {
+                if terminate.load(Ordering::SeqCst) {
+                    break None;
+                }
+                match heads.recv_timeout(SIGNAL_CHECK_INTERVAL) {
+                    Ok(head) => {
+                        // heads pushed during the last batch
+                        // are covered by a single new one
+                        let head = heads.try_iter().last().unwrap_or(head);
+                        println!("[scan] New head {}", head);
+                        batch();
+                    },
+                    Err(RecvTimeoutError::Timeout) => {},
+                    Err(RecvTimeoutError::Disconnected) => {
+                        break Some(Error::Custom(String::from("New heads subscription closed")));
+                    }
+                }
+            }
         }
-    }));
-    loop {
-        sched.tick();
-        std::thread::sleep(Duration::from_secs(5));
+    };
+    let _ = fs::remove_file(&paths.socket);
+    println!("[scan] Stopped, checkpoints saved");
+
+    // an exhausted error budget also stops the
+    // scanner, which then exits with its error
+    match failure.or(stopped_by) {
+        Some(e) => Err(e),
+        None => Ok(())
     }
 }
 
-fn is_my_tx(
+fn scan_once(
+    client: &Client,
+    storage_dir: &PathBuf,
+    targets: &mut [ScanTarget],
+    confirmations: u64,
+    source: &dyn AnnouncementSource
+) -> Result<(), Error> {
+    // roll back first the keys whose recently
+    // processed blocks were reorganised
+    for target in targets.iter_mut() {
+        if let Some(rolled_back) = rollback(&client, &storage_dir, &target.master_address, &target.checkpoint, source)? {
+            target.checkpoint = rolled_back;
+        }
+    }
+
+    // announcements are only processed once they
+    // are buried under `confirmations` blocks
+    let confirmed_block = source.head(&client)?.saturating_sub(source.confirmations(confirmations));
+    let from_block = match targets.iter().map(|target| target.checkpoint.block).min() {
+        Some(from_block) if from_block < confirmed_block => from_block,
+        _ => return Ok(())
+    };
+
+    // announcements of both the RsTx contract and the EIP-5564
+    // announcer, the complete set of the range, fetched once
+    let txs = source.announcements(&client, from_block, confirmed_block)?;
+    let block_hash = source.block_hash(&client, confirmed_block)?;
+
+    // ECDH per key per announcement, each key
+    // only looks past its own checkpoint
+    for target in targets.iter_mut().filter(|target| target.checkpoint.block < confirmed_block) {
+        let my_txs: Vec<PendingMatch> = txs
+            .iter()
+            .filter(|tx| tx.block.as_u64() > target.checkpoint.block)
+            .filter_map(|tx| is_my_tx(tx, &target.scan_keypair, &target.spend_public_key).ok())
+            .map(|tx| PendingMatch::from_tx(&tx))
+            .collect();
+
+        // advance the checkpoint to the confirmed block,
+        // committing its matches in the same write
+        let mut next = target.checkpoint.clone();
+        if let Some(last_match) = my_txs.last() {
+            next.last_match = Some(last_match.announcement.clone());
+        }
+        next.pending.extend(my_txs);
+        next.advance(confirmed_block, block_hash);
+        checkpoint::store(&storage_dir, &target.master_address, &next)?;
+        target.checkpoint = next;
+    }
+
+    Ok(())
+}
+
+pub fn rollback(
+    client: &Client,
+    storage_dir: &PathBuf,
+    master_address: &str,
+    checkpoint: &Checkpoint,
+    source: &dyn AnnouncementSource
+) -> Result<Option<Checkpoint>, Error> {
+    let fork_block = match find_fork(&client, &checkpoint, source)? {
+        Some(fork_block) => fork_block,
+        None => return Ok(None)
+    };
+    eprintln!("[scan] Chain reorganisation after block {}, re-scanning", fork_block);
+
+    // stealth keys already derived from orphaned announcements are
+    // marked as such, re-included ones are found by the re-scan
+    for entry in index::mark_orphaned(&storage_dir, &master_address, fork_block)? {
+        println!("Orphaned payment: {}", entry.address);
+        println!("Nonce point: {}", entry.nonce_point);
+    }
+    payments::mark_orphaned(&storage_dir, &master_address, fork_block)?;
+
+    let mut rolled_back = checkpoint.clone();
+    rolled_back.rollback(fork_block);
+    checkpoint::store(&storage_dir, &master_address, &rolled_back)?;
+
+    Ok(Some(rolled_back))
+}
+
+/// Last recently processed block still on the
+/// canonical chain, if the chain reorganised
+fn find_fork(client: &Client, checkpoint: &Checkpoint, source: &dyn AnnouncementSource) -> Result<Option<u64>, Error> {
+    let newest = checkpoint.block_hashes.len();
+    for (i, block_hash) in checkpoint.block_hashes.iter().enumerate().rev() {
+        if source.block_hash(&client, block_hash.number)? == Some(block_hash.hash) {
+            if i + 1 == newest {
+                return Ok(None);
+            }
+            return Ok(Some(block_hash.number));
+        }
+    }
+
+    // a reorg deeper than the recent window
+    // re-scans from before the whole window
+    Ok(checkpoint.block_hashes.first().map(|oldest| oldest.number.saturating_sub(1)))
+}
+
+/// Handles the pending matches of a master key, the
+/// unhandled ones stay pending and are retried
+pub fn handle_matches(
+    client: &Client,
+    storage_dir: &PathBuf,
+    passphrase: &str,
+    target: &mut ScanTarget,
+    config: &ScanConfig
+) {
+    let scan_keypair = &target.scan_keypair;
+    let spend_public_key = &target.spend_public_key;
+    let sweep_policy = config.sweep_policy(&target.master_address);
+
+    // master keys without a separate scan key can claim payments
+    // directly from the daemon, and so do the ones swept by it
+    let spend_address = public_to_address(&spend_public_key);
+    let can_spend = scan_keypair.address() == spend_address || sweep_policy.is_some();
+
+    // matches whose notifications failed wait for their backoff
+    let now = unix_timestamp();
+    let (due, mut remaining): (Vec<PendingMatch>, Vec<PendingMatch>) = target.checkpoint.pending
+        .drain(..)
+        .partition(|pending_match| pending_match.retry_at <= now);
+
+    // derive the stealth addresses without any RPC
+    let mut derived = vec![];
+    for pending_match in due {
+        let address = if can_spend {
+            receive::derive(
+                &storage_dir,
+                &target.master_address,
+                &passphrase,
+                &pending_match.nonce_point.as_str(),
+                pending_match.scheme,
+                Some(&pending_match.announcement)
+            )
+        } else {
+            receive::stealth_address(&spend_public_key, &scan_keypair, &pending_match.nonce_point.as_str(), pending_match.scheme)
+        };
+        match address {
+            Ok(address) => derived.push((pending_match, address)),
+            Err(e) => {
+                eprintln!("error = {:?}", e);
+                remaining.push(pending_match);
+            }
+        }
+    }
+
+    // then fetch all of their balances in a single batch
+    let addresses: Vec<H160> = derived.iter().map(|(_, address)| *address).collect();
+    let balances = match client.balances(&addresses) {
+        Ok(balances) => balances.into_iter().map(Some).collect(),
+        Err(e) => {
+            eprintln!("[scan] Error fetching balances: {:?}", e);
+            vec![None; addresses.len()]
+        }
+    };
+
+    for ((mut pending_match, address), balance) in derived.into_iter().zip(balances) {
+        println!("Master address: {}", target.master_address);
+        if can_spend {
+            println!("Successfully claimed receipt");
+        } else {
+            println!("Detected payment, claim it with `receive`");
+            println!("Nonce point: {}", pending_match.nonce_point);
+        }
+        println!("Recipient address: {:?}", address);
+        match balance {
+            Some(balance) => println!("Recipient balance: {:?}", balance),
+            None => println!("Recipient balance: unknown")
+        }
+
+        // a funded stealth address confirms the payment, its
+        // announcement may be mined before the funding tx
+        let funded = balance.map_or(false, |balance| !balance.is_zero());
+        let payment = Payment {
+            master_address: target.master_address.clone(),
+            stealth_address: format!("{:?}", address),
+            amount: balance.map(|balance| balance.to_string()),
+            announcement_tx: pending_match.announcement.tx_hash.clone(),
+            funding_tx: None,
+            block: pending_match.announcement.block,
+            status: if funded { PaymentStatus::Confirmed } else { PaymentStatus::Detected }
+        };
+        if let Err(e) = payments::record_payment(&storage_dir, &payment) {
+            eprintln!("[scan] Error recording payment {}: {:?}", payment.announcement_tx, e);
+            remaining.push(pending_match);
+            continue;
+        }
+
+        // only the sinks not notified yet are, so that
+        // a failing sink never repeats the healthy ones
+        let notification = PaymentNotification {
+            master_address: target.master_address.clone(),
+            label: config.label(&target.master_address),
+            stealth_address: address,
+            amount: balance.map(|balance| balance.to_string()),
+            tx_hash: pending_match.announcement.tx_hash.clone(),
+            block: pending_match.announcement.block,
+            nonce_point: pending_match.nonce_point.clone(),
+            scheme: pending_match.scheme,
+            claimed: can_spend
+        };
+        let sinks: Vec<&NotificationSink> = config.notifications
+            .iter()
+            .filter(|sink| !pending_match.notified.contains(sink))
+            .collect();
+        let failed = notify::notify(&client, &sinks, &notification);
+        for sink in sinks.into_iter().filter(|sink| !failed.contains(sink)) {
+            pending_match.notified.push(sink.clone());
+        }
+
+        // failed sinks are retried with exponential
+        // backoff, and given up after a few attempts
+        if !failed.is_empty() {
+            pending_match.attempts += 1;
+            if pending_match.attempts < notify::MAX_NOTIFY_ATTEMPTS {
+                pending_match.retry_at = now + notify::retry_delay(pending_match.attempts);
+                remaining.push(pending_match);
+                continue;
+            }
+            eprintln!(
+                "[scan] Giving up notifying {:?} of {} after {} attempts",
+                failed,
+                notification.tx_hash,
+                pending_match.attempts
+            );
+        }
+        if let Some(policy) = sweep_policy {
+            target.checkpoint.sweeps.push(policy.schedule(address, &pending_match.announcement));
+        }
+    }
+
+    target.checkpoint.pending = remaining;
+}
+
+/// Confirms the detected payments of a master key
+/// whose stealth addresses were funded since
+fn confirm_detected(client: &Client, storage_dir: &PathBuf, master_address: &str) -> Result<(), Error> {
+    let detected = payments::detected(&storage_dir, &master_address)?;
+    if detected.is_empty() {
+        return Ok(());
+    }
+
+    let addresses = detected
+        .iter()
+        .map(|payment| H160::from_str(payment.stealth_address.trim_start_matches("0x")))
+        .collect::<Result<Vec<H160>, _>>()
+        .map_err(|e| Error::Custom(format!("Invalid stealth address in the payments: {:?}", e)))?;
+    let balances = client.balances(&addresses)?;
+
+    for ((payment, address), balance) in detected.into_iter().zip(addresses).zip(balances) {
+        if balance.is_zero() {
+            continue;
+        }
+        println!("Confirmed payment to {:?}, balance: {:?}", address, balance);
+        let confirmed = Payment {
+            amount: Some(balance.to_string()),
+            status: PaymentStatus::Confirmed,
+            ..payment
+        };
+        payments::record_payment(&storage_dir, &confirmed)?;
+    }
+
+    Ok(())
+}
+
+/// Fills in the funding txs of the funded payments of a master
+/// key on a best effort basis, the ones not found stay empty
+fn fill_funding_txs(client: &Client, storage_dir: &PathBuf, master_address: &str, looked_up: &mut HashSet<String>) {
+    let payments = match payments::without_funding_tx(&storage_dir, &master_address) {
+        Ok(payments) => payments,
+        Err(e) => {
+            eprintln!("[scan] Error reading the payments of {}: {:?}", master_address, e);
+            return;
+        }
+    };
+
+    let unresolved = payments
+        .into_iter()
+        .filter(|payment| !looked_up.contains(&payment.announcement_tx))
+        .take(FUNDING_TXS_PER_BATCH)
+        .collect::<Vec<Payment>>();
+    for payment in unresolved {
+        looked_up.insert(payment.announcement_tx.clone());
+        let address = H160::from_str(payment.stealth_address.trim_start_matches("0x"));
+        let announcement_tx = H256::from_str(payment.announcement_tx.trim_start_matches("0x"));
+        let funding_tx = match (address, announcement_tx) {
+            (Ok(address), Ok(announcement_tx)) => client.funding_tx(address, announcement_tx),
+            _ => continue
+        };
+        match funding_tx {
+            Ok(Some(funding_tx)) => {
+                if let Err(e) = payments::set_funding_tx(&storage_dir, &payment, &format!("{:?}", funding_tx)) {
+                    eprintln!("[scan] Error recording the funding tx of {}: {:?}", payment.stealth_address, e);
+                }
+            },
+            Ok(None) => {},
+            Err(e) => eprintln!("[scan] Error finding the funding tx of {}: {:?}", payment.stealth_address, e)
+        }
+    }
+}
+
+pub fn is_my_tx(
+    tx: &RsTxTransaction,
+    scan_keypair: &KeyPair,
+    spend_public_key: &Public
+) -> Result<RsTxTransaction, Error> {
+    match tx.scheme {
+        StealthScheme::RsTx => is_my_rs_tx(tx, scan_keypair, &public_to_address(&spend_public_key)),
+        StealthScheme::Eip5564 => is_my_announcement(tx, scan_keypair, spend_public_key)
+    }
+}
+
+fn is_my_announcement(
     tx: &RsTxTransaction,
-    keypair: &KeyPair
+    scan_keypair: &KeyPair,
+    spend_public_key: &Public
+) -> Result<RsTxTransaction, Error> {
+    let (ephemeral_public_key, _) = key::public_key_from_str(&tx.nonce_point)?;
+    let shared_secret = tx.scheme.shared_secret(&ephemeral_public_key, scan_keypair.secret())?;
+
+    // the view tag (first metadata byte) rules out
+    // most foreign announcements with a single byte
+    if tx.metadata.first().cloned() != Some(tx.scheme.view_tag(&shared_secret)) {
+        return Err(Error::Custom(String::from("[Dummy error] View tag mismatch")));
+    }
+
+    // if the announced stealth address is derived
+    // from own spend key, include this tx to be received
+    let stealth_public_key = key::stealth_public(&spend_public_key, &shared_secret)?;
+    let stealth_address = convert_h160(public_to_address(&stealth_public_key));
+
+    if tx.stealth_address == Some(stealth_address) {
+        let my_tx = tx.clone();
+        Ok(my_tx)
+    } else {
+        Err(Error::Custom(String::from("[Dummy error] None of the tx were yours")))
+    }
+}
+
+fn is_my_rs_tx(
+    tx: &RsTxTransaction,
+    scan_keypair: &KeyPair,
+    spend_address: &Address
 ) -> Result<RsTxTransaction, Error> {
     // check if the encrypted recipient when decrypted is actually
-    // the address of your own keypair. if not, return Err
+    // the address of your own spend key. if not, return Err
     let (nonce_point, _) = key::public_key_from_str(&tx.nonce_point)?;
-    let mut ecdh_shared_secret = nonce_point.clone();
-    ec_math_utils::public_mul_secret(&mut ecdh_shared_secret, &keypair.secret())?;
-    let shared_secret = ecdh_shared_secret.keccak256();
+    let shared_secret = tx.scheme.shared_secret(&nonce_point, scan_keypair.secret())?;
+
+    // a view tag mismatch rules out the tx right after
+    // the ECDH step, without fetching or decrypting it
+    let payload = RsTxPayload::decode(&tx.encrypted_recipient)?;
+    if let Some(view_tag) = payload.view_tag {
+        if view_tag != tx.scheme.view_tag(&shared_secret) {
+            return Err(Error::Custom(String::from("[Dummy error] View tag mismatch")));
+        }
+    }
+
     let key = GenericArray::clone_from_slice(&shared_secret);
     let aead = Aes256Gcm::new(key);
 
-    // instantiate web3
-    let (_eloop, transport) = web3::transports::Http::new("http://127.0.0.1:8545").unwrap();
-    let web3 = web3::Web3::new(transport);
-
-    // get tx nonce for calculating encryption nonce
-    let tx_hash = convert_str_h256(&tx.id)?;
-    let web3_tx = web3.eth()
-        .transaction(Hash(H256::from(tx_hash)))
-        .wait()
-        .unwrap()
-        .unwrap();
-
-    // decrypt the recipient address
-    let tx_nonce = web3_tx.nonce;
-    let mut tx_nonce_slice = [0u8; 32];
-    tx_nonce.to_big_endian(&mut tx_nonce_slice);
-    let popped_tx_nonce = {
-		let mut temp = [0u8; 12];
-		(&mut temp[0..12]).copy_from_slice(&tx_nonce_slice[0..12]);
-		temp
-	};
-    let encryption_nonce = GenericArray::from_slice(&popped_tx_nonce);
-    let decrypted_recipient = aead.decrypt(encryption_nonce, tx.encrypted_recipient.as_ref())?;
+    // versioned payloads carry their encryption nonce, legacy
+    // ones use a fixed one, so no tx is fetched from the node
+    let encryption_nonce_bytes = payload.encryption_nonce();
+    let encryption_nonce = GenericArray::from_slice(&encryption_nonce_bytes);
+    let decrypted_recipient = aead.decrypt(encryption_nonce, payload.ciphertext.as_ref())?;
 
     // if intended address matches own address
     // include this tx to be received
     let intended_recipient_address = hex::encode(decrypted_recipient);
-    let own_address = format!("{:x}", keypair.address());
+    let own_address = format!("{:x}", spend_address);
 
     if intended_recipient_address == own_address {
         let my_tx = tx.clone();
@@ -163,3 +772,207 @@ fn is_my_tx(
         Err(Error::Custom(String::from("[Dummy error] None of the tx were yours")))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use parity_crypto::publickey::{Generator, Random};
+
+    use web3::types::{H256, U64};
+
+    use crate::index::Announcement;
+    use crate::source::{DumpRecord, DumpSource};
+
+    /// Chain of a test, whose blocks after `fork`
+    /// are replaced by the ones of another branch
+    struct FakeSource {
+        head: u64,
+        txs: Vec<RsTxTransaction>,
+        fork: Option<u64>,
+    }
+
+    impl AnnouncementSource for FakeSource {
+        fn announcements(&self, _client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+            let txs = self.txs
+                .iter()
+                .filter(|tx| tx.block.as_u64() > block_gt && tx.block.as_u64() <= block_lte)
+                .cloned()
+                .collect();
+
+            Ok(txs)
+        }
+
+        fn head(&self, _client: &Client) -> Result<u64, Error> {
+            Ok(self.head)
+        }
+
+        fn block_hash(&self, _client: &Client, block_number: u64) -> Result<Option<H256>, Error> {
+            let branch = match self.fork {
+                Some(fork) if block_number > fork => 1 << 32,
+                _ => 0
+            };
+
+            Ok(Some(H256::from_low_u64_be(branch + block_number)))
+        }
+    }
+
+    /// EIP-5564 announcement of a payment to the given keys
+    fn announcement(id: &str, block: u64, scan_keypair: &KeyPair, spend_public_key: &Public) -> RsTxTransaction {
+        let ephemeral = Random.generate();
+        let shared_secret = StealthScheme::Eip5564.shared_secret(scan_keypair.public(), ephemeral.secret()).unwrap();
+        let stealth_public_key = key::stealth_public(&spend_public_key, &shared_secret).unwrap();
+
+        RsTxTransaction {
+            id: String::from(id),
+            block: U64::from(block),
+            scheme: StealthScheme::Eip5564,
+            nonce_point: key::to_secp256k1_public(ephemeral.public()).unwrap().to_string(),
+            encrypted_recipient: vec![],
+            stealth_address: Some(convert_h160(public_to_address(&stealth_public_key))),
+            metadata: vec![StealthScheme::Eip5564.view_tag(&shared_secret)]
+        }
+    }
+
+    fn target(master_address: &str, scan_keypair: &KeyPair, spend_keypair: &KeyPair, block: u64) -> ScanTarget {
+        ScanTarget {
+            master_address: String::from(master_address),
+            scan_keypair: scan_keypair.clone(),
+            spend_public_key: *spend_keypair.public(),
+            checkpoint: Checkpoint {
+                block: block,
+                ..Default::default()
+            }
+        }
+    }
+
+    fn pending_ids(target: &ScanTarget) -> Vec<String> {
+        target.checkpoint.pending
+            .iter()
+            .map(|pending_match| pending_match.announcement.tx_hash.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_scan_dump() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let scan_keypair = Random.generate();
+        let spend_keypair = Random.generate();
+        let other_keypair = Random.generate();
+        let txs = vec![
+            announcement("0x01", 3, &scan_keypair, spend_keypair.public()),
+            announcement("0x02", 7, &other_keypair, other_keypair.public()),
+            announcement("0x03", 10, &scan_keypair, spend_keypair.public())
+        ];
+
+        let dump_path = temp_dir.path().join("announcements.jsonl");
+        let mut dump_file = File::create(&dump_path).unwrap();
+        for tx in &txs {
+            let record = DumpRecord {
+                id: tx.id.clone(),
+                block: tx.block.as_u64(),
+                scheme: tx.scheme,
+                nonce_point: tx.nonce_point.clone(),
+                encrypted_recipient: String::new(),
+                stealth_address: tx.stealth_address.map(|address| format!("{:?}", address)),
+                metadata: hex::encode(&tx.metadata)
+            };
+            writeln!(dump_file, "{}", serde_json::to_string(&record).unwrap()).unwrap();
+        }
+        let source = DumpSource::new(dump_path).unwrap();
+
+        // a key without a checkpoint scans the whole dump, up
+        // to its last block, whatever the confirmation depth
+        let client = Client::new().unwrap();
+        let start_block = source.start_block(&client, 12).unwrap();
+        let mut targets = vec![target("0xaa", &scan_keypair, &spend_keypair, start_block)];
+        scan_once(&client, &storage_dir, &mut targets, 12, &source).unwrap();
+        assert!(targets[0].checkpoint.block == 10);
+        assert!(pending_ids(&targets[0]) == vec!["0x01", "0x03"]);
+        assert!(checkpoint::load(&storage_dir, "0xaa").unwrap().unwrap().block == 10);
+    }
+    #[test]
+    fn test_scan_rollback() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let scan_keypair = Random.generate();
+        let spend_keypair = Random.generate();
+        let mut source = FakeSource {
+            head: 10,
+            txs: vec![
+                announcement("0x01", 5, &scan_keypair, spend_keypair.public()),
+                announcement("0x02", 15, &scan_keypair, spend_keypair.public())
+            ],
+            fork: None
+        };
+
+        let client = Client::new().unwrap();
+        let mut targets = vec![target("0xaa", &scan_keypair, &spend_keypair, 0)];
+        scan_once(&client, &storage_dir, &mut targets, 0, &source).unwrap();
+        source.head = 20;
+        scan_once(&client, &storage_dir, &mut targets, 0, &source).unwrap();
+        assert!(targets[0].checkpoint.block == 20 && targets[0].checkpoint.block_hashes.len() == 2);
+        assert!(pending_ids(&targets[0]) == vec!["0x01", "0x02"]);
+        assert!(find_fork(&client, &targets[0].checkpoint, &source).unwrap().is_none());
+
+        // the payment of block 15 was claimed before the reorg
+        let announcement_15 = Announcement { tx_hash: String::from("0x02"), block: 15 };
+        index::record(&storage_dir, "0x0f", "0xaa", "02cc", StealthScheme::Eip5564, Some(&announcement_15)).unwrap();
+
+        // the chain reorganises after block 10, the payment of
+        // block 15 is dropped and another one mined at block 18
+        source.fork = Some(10);
+        source.head = 25;
+        source.txs.pop();
+        source.txs.push(announcement("0x03", 18, &scan_keypair, spend_keypair.public()));
+        assert!(find_fork(&client, &targets[0].checkpoint, &source).unwrap() == Some(10));
+
+        scan_once(&client, &storage_dir, &mut targets, 0, &source).unwrap();
+        assert!(targets[0].checkpoint.block == 25);
+        assert!(pending_ids(&targets[0]) == vec!["0x01", "0x03"]);
+        assert!(index::load(&storage_dir).unwrap().get("0x0f").unwrap().orphaned);
+
+        let stored = checkpoint::load(&storage_dir, "0xaa").unwrap().unwrap();
+        assert!(stored.block == 25 && stored.pending.len() == 2);
+        assert!(stored.block_hashes.iter().all(|block_hash| block_hash.number <= 10 || block_hash.number == 25));
+    }
+
+    #[test]
+    fn test_scan_lagging_key() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let (scan_a, spend_a) = (Random.generate(), Random.generate());
+        let (scan_b, spend_b) = (Random.generate(), Random.generate());
+        let source = FakeSource {
+            head: 30,
+            txs: vec![
+                announcement("0x01", 5, &scan_a, spend_a.public()),
+                announcement("0x02", 5, &scan_b, spend_b.public()),
+                announcement("0x03", 25, &scan_a, spend_a.public()),
+                announcement("0x04", 25, &scan_b, spend_b.public())
+            ],
+            fork: None
+        };
+
+        // the key added later is scanned from its own checkpoint, in
+        // the same pass, without re-matching the other key's past
+        let client = Client::new().unwrap();
+        let mut targets = vec![
+            target("0xaa", &scan_a, &spend_a, 20),
+            target("0xbb", &scan_b, &spend_b, 0)
+        ];
+        scan_once(&client, &storage_dir, &mut targets, 2, &source).unwrap();
+        assert!(targets.iter().all(|target| target.checkpoint.block == 28));
+        assert!(pending_ids(&targets[0]) == vec!["0x03"]);
+        assert!(pending_ids(&targets[1]) == vec!["0x02", "0x04"]);
+
+        // nothing is left to scan below the confirmed head
+        scan_once(&client, &storage_dir, &mut targets, 2, &source).unwrap();
+        assert!(pending_ids(&targets[1]).len() == 2);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::str::FromStr;
+
+use parity_crypto::Keccak256;
+use parity_crypto::publickey::{Public, Secret};
+use parity_crypto::publickey::ec_math_utils;
+
+use serde::{Deserialize, Serialize};
+
+use web3::types::U256;
+
+use crate::errors::Error;
+use crate::key;
+
+/// Token address EIP-5564 metadata uses for native ether
+const ETH_TOKEN: [u8; 20] = [0xee; 20];
+
+/// Function selector EIP-5564 metadata uses for native ether
+const ETH_SELECTOR: [u8; 4] = [0xee; 4];
+
+/// Stealth address scheme of an announcement
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StealthScheme {
+    /// RsTx contract, recipient encrypted with AES-GCM,
+    /// shared secret hashed from the uncompressed point.
+    /// Its view tag is optional in the payload
+    RsTx,
+    /// EIP-5564 secp256k1 (schemeId 1) with view tags, shared
+    /// secret hashed from the compressed point
+    Eip5564,
+}
+
+impl Default for StealthScheme {
+    fn default() -> StealthScheme {
+        StealthScheme::RsTx
+    }
+}
+
+impl StealthScheme {
+    /// EIP-5564 scheme id (RsTx predates EIP-5564 and has none)
+    pub fn id(&self) -> Option<u64> {
+        match self {
+            StealthScheme::RsTx => None,
+            StealthScheme::Eip5564 => Some(1)
+        }
+    }
+
+    /// Scheme used by default for a recipient, `st:` meta-addresses
+    /// follow EIP-5564, bare public keys the legacy RsTx scheme
+    pub fn for_recipient(to: &str) -> StealthScheme {
+        if to.starts_with("st:") {
+            StealthScheme::Eip5564
+        } else {
+            StealthScheme::RsTx
+        }
+    }
+
+    pub fn shared_secret(&self, public: &Public, secret: &Secret) -> Result<[u8; 32], Error> {
+        match self {
+            StealthScheme::RsTx => key::ecdh_shared_secret(&public, &secret),
+            StealthScheme::Eip5564 => {
+                let mut ecdh_shared_secret = public.clone();
+                ec_math_utils::public_mul_secret(&mut ecdh_shared_secret, &secret)?;
+                let compressed = key::to_secp256k1_public(&ecdh_shared_secret)?.serialize();
+                Ok(compressed.keccak256())
+            }
+        }
+    }
+
+    /// One byte view tag of a shared secret. The RsTx shared
+    /// secret is also the AES key, so its tag is taken from
+    /// the hash of the secret to not reveal any key bits
+    pub fn view_tag(&self, shared_secret: &[u8; 32]) -> u8 {
+        match self {
+            StealthScheme::RsTx => shared_secret.keccak256()[0],
+            StealthScheme::Eip5564 => shared_secret[0]
+        }
+    }
+}
+
+impl fmt::Display for StealthScheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StealthScheme::RsTx => write!(f, "rstx"),
+            StealthScheme::Eip5564 => write!(f, "eip5564")
+        }
+    }
+}
+
+impl FromStr for StealthScheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "rstx" => Ok(StealthScheme::RsTx),
+            "eip5564" | "1" => Ok(StealthScheme::Eip5564),
+            _ => Err(Error::Custom(format!("Unknown stealth scheme: {}", s)))
+        }
+    }
+}
+
+/// EIP-5564 metadata of a native ether transfer: view tag,
+/// 0xeeeeeeee, the 0xeee..ee token address and the amount
+pub fn eip5564_metadata(view_tag: u8, amount: U256) -> Vec<u8> {
+    let mut amount_bytes = [0u8; 32];
+    amount.to_big_endian(&mut amount_bytes);
+
+    let mut metadata = Vec::with_capacity(57);
+    metadata.push(view_tag);
+    metadata.extend_from_slice(&ETH_SELECTOR);
+    metadata.extend_from_slice(&ETH_TOKEN);
+    metadata.extend_from_slice(&amount_bytes);
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use parity_crypto::publickey::{Generator, KeyPair, Random};
+    use parity_crypto::publickey::public_to_address;
+
+    #[test]
+    fn test_shared_secret_per_scheme() {
+        let scan = Random.generate();
+        let nonce = Random.generate();
+
+        for scheme in &[StealthScheme::RsTx, StealthScheme::Eip5564] {
+            let sender_secret = scheme.shared_secret(scan.public(), nonce.secret()).unwrap();
+            let recipient_secret = scheme.shared_secret(nonce.public(), scan.secret()).unwrap();
+            assert!(sender_secret == recipient_secret);
+        }
+
+        let rs_tx_secret = StealthScheme::RsTx.shared_secret(scan.public(), nonce.secret()).unwrap();
+        let eip5564_secret = StealthScheme::Eip5564.shared_secret(scan.public(), nonce.secret()).unwrap();
+        assert!(rs_tx_secret != eip5564_secret);
+        assert!(StealthScheme::Eip5564.view_tag(&eip5564_secret) == eip5564_secret[0]);
+        assert!(StealthScheme::RsTx.view_tag(&rs_tx_secret) == rs_tx_secret.keccak256()[0]);
+    }
+
+    // worked out with an independent secp256k1 and keccak256
+    // implementation following the EIP-5564 reference: the shared
+    // point is hashed compressed (33 bytes), and the view tag is
+    // the first byte of that hash
+    #[test]
+    fn test_eip5564_known_answer() {
+        let spend = KeyPair::from_secret(Secret::from_str("84892158202a1111f745b88919dceaf31d73227f6d2cf6028d8eb5efac205851").unwrap()).unwrap();
+        let view = KeyPair::from_secret(Secret::from_str("aa1d391582d6715cba6d12583b8a98d6faa37e5476f27805ce02b81d94bbaa09").unwrap()).unwrap();
+        let ephemeral = KeyPair::from_secret(Secret::from_str("c66882889d54f2896baa87e82ec3b329f343027ea92c90c19fa73ac584724622").unwrap()).unwrap();
+        assert!(key::to_secp256k1_public(spend.public()).unwrap().to_string() == "02838420f4b5c08cea726a0658dd0d32dde1c0b284a522b5473b7b25103aa1515f");
+        assert!(key::to_secp256k1_public(view.public()).unwrap().to_string() == "02277a49542ae9e0cb7eb2a81c6f364c74a3746e5d04799cc348a2330b67373c49");
+        assert!(key::to_secp256k1_public(ephemeral.public()).unwrap().to_string() == "027e02c9553d618c7f19d97cbc69be5857269f17aad3cf751fd3c547eda1f9aee2");
+
+        // the sender hashes the ephemeral key against the
+        // viewing key, the recipient the other way around
+        let shared_secret = StealthScheme::Eip5564.shared_secret(view.public(), ephemeral.secret()).unwrap();
+        assert!(hex::encode(shared_secret) == "a332f58e4dd676a007ff094463bb125a3dfcd7cc48b0f136965aaf85028d56c4");
+        assert!(StealthScheme::Eip5564.shared_secret(ephemeral.public(), view.secret()).unwrap() == shared_secret);
+        assert!(StealthScheme::Eip5564.view_tag(&shared_secret) == 0xa3);
+
+        let stealth_public = key::stealth_public(spend.public(), &shared_secret).unwrap();
+        assert!(format!("{:?}", public_to_address(&stealth_public)) == "0x81a0b1065dd630a027d38975b01f681f3d1b723a");
+    }
+
+    #[test]
+    fn test_eip5564_metadata() {
+        let metadata = eip5564_metadata(0xab, U256::from(1000));
+        assert!(metadata.len() == 57);
+        assert!(metadata[0] == 0xab);
+        assert!(metadata[1..25].iter().all(|b| *b == 0xee));
+        assert!(metadata[55..57] == [0x03, 0xe8]);
+    }
+}
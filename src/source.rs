@@ -0,0 +1,293 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use hex::FromHex;
+
+use serde::{Deserialize, Serialize};
+
+use web3::types::{H160, H256, U64};
+
+use crate::client::Client;
+use crate::errors::Error;
+use crate::query;
+use crate::query::RsTxTransaction;
+use crate::scheme::StealthScheme;
+use crate::utils::convert_u64_i64;
+
+/// Source of stealth announcements for the scanner
+pub trait AnnouncementSource: Send + Sync {
+    /// Announcements of both schemes in the block
+    /// range `(block_gt, block_lte]`, ordered by block
+    fn announcements(&self, client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error>;
+
+    /// Latest block the source has announcements up to
+    fn head(&self, client: &Client) -> Result<u64, Error>;
+
+    /// Hash of a block, if the source follows the chain
+    fn block_hash(&self, client: &Client, block_number: u64) -> Result<Option<H256>, Error>;
+
+    /// Blocks kept below the head before scanning
+    /// them, out of the requested confirmation depth
+    fn confirmations(&self, requested: u64) -> u64 {
+        requested
+    }
+
+    /// Block a master key without a checkpoint starts scanning after
+    fn start_block(&self, client: &Client, confirmations: u64) -> Result<u64, Error> {
+        Ok(self.head(&client)?.saturating_sub(self.confirmations(confirmations)))
+    }
+}
+
+/// Kind of announcement source, as configured on the command line
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourceKind {
+    /// The RsTx subgraph (The Graph)
+    Subgraph,
+    /// Contract logs from the JSON-RPC node
+    Rpc,
+    /// A local JSONL or CSV announcement dump
+    Dump,
+}
+
+impl FromStr for SourceKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "subgraph" => Ok(SourceKind::Subgraph),
+            "rpc" => Ok(SourceKind::Rpc),
+            "dump" => Ok(SourceKind::Dump),
+            _ => Err(Error::Custom(format!("Unknown announcement source: {}", s)))
+        }
+    }
+}
+
+pub fn new(kind: SourceKind, dump_file: &Option<PathBuf>) -> Result<Box<dyn AnnouncementSource>, Error> {
+    match kind {
+        SourceKind::Subgraph => Ok(Box::new(SubgraphSource)),
+        SourceKind::Rpc => Ok(Box::new(RpcSource)),
+        SourceKind::Dump => {
+            let path = dump_file
+                .clone()
+                .ok_or_else(|| Error::Custom(String::from("A dump source needs a dump file")))?;
+            Ok(Box::new(DumpSource::new(path)?))
+        }
+    }
+}
+
+/// RsTx transactions from the subgraph, EIP-5564
+/// announcements from the announcer contract logs
+pub struct SubgraphSource;
+
+impl AnnouncementSource for SubgraphSource {
+    fn announcements(&self, client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+        let (b_gt, b_lte) = match (convert_u64_i64(block_gt), convert_u64_i64(block_lte)) {
+            (Some(b_gt), Some(b_lte)) => (b_gt, b_lte),
+            _ => return Err(Error::Custom(String::from("Block number out of range of the subgraph")))
+        };
+        let mut txs = query::query(&client, b_gt, b_lte)?;
+        txs.extend(query::query_announcements(&client, block_gt, block_lte)?);
+        txs.sort_by_key(|tx| tx.block);
+
+        Ok(txs)
+    }
+
+    fn head(&self, client: &Client) -> Result<u64, Error> {
+        // the subgraph may lag behind the node
+        let subgraph_block = query::subgraph_block(&client)?;
+        let latest_block = query::latest_block(&client)?;
+
+        Ok(cmp::min(subgraph_block, latest_block))
+    }
+
+    fn block_hash(&self, client: &Client, block_number: u64) -> Result<Option<H256>, Error> {
+        query::block_hash(&client, block_number)
+    }
+}
+
+/// Announcements of both schemes read from contract
+/// logs, so a plain JSON-RPC node is enough
+pub struct RpcSource;
+
+impl AnnouncementSource for RpcSource {
+    fn announcements(&self, client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+        let mut txs = query::query_logs(&client, block_gt, block_lte)?;
+        txs.extend(query::query_announcements(&client, block_gt, block_lte)?);
+        txs.sort_by_key(|tx| tx.block);
+
+        Ok(txs)
+    }
+
+    fn head(&self, client: &Client) -> Result<u64, Error> {
+        query::latest_block(&client)
+    }
+
+    fn block_hash(&self, client: &Client, block_number: u64) -> Result<Option<H256>, Error> {
+        query::block_hash(&client, block_number)
+    }
+}
+
+/// Announcements from a local dump, one record per line (JSONL)
+/// or per row (CSV, picked by the `.csv` extension)
+pub struct DumpSource {
+    txs: Vec<RsTxTransaction>,
+}
+
+/// A single announcement of a dump, with hex encoded bytes
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DumpRecord {
+    pub id: String,
+    pub block: u64,
+    #[serde(default)]
+    pub scheme: StealthScheme,
+    pub nonce_point: String,
+    #[serde(default)]
+    pub encrypted_recipient: String,
+    #[serde(default)]
+    pub stealth_address: Option<String>,
+    #[serde(default)]
+    pub metadata: String,
+}
+
+impl DumpSource {
+    pub fn new(path: PathBuf) -> Result<DumpSource, Error> {
+        let records = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => read_csv(&path)?,
+            _ => read_jsonl(&path)?
+        };
+
+        let mut txs = records
+            .iter()
+            .map(|record| record.to_transaction())
+            .collect::<Result<Vec<RsTxTransaction>, Error>>()?;
+        txs.sort_by_key(|tx| tx.block);
+
+        Ok(DumpSource { txs: txs })
+    }
+}
+
+impl AnnouncementSource for DumpSource {
+    fn announcements(&self, _client: &Client, block_gt: u64, block_lte: u64) -> Result<Vec<RsTxTransaction>, Error> {
+        let txs = self.txs
+            .iter()
+            .filter(|tx| tx.block.as_u64() > block_gt && tx.block.as_u64() <= block_lte)
+            .cloned()
+            .collect();
+
+        Ok(txs)
+    }
+
+    fn head(&self, _client: &Client) -> Result<u64, Error> {
+        Ok(self.txs.last().map_or(0, |tx| tx.block.as_u64()))
+    }
+
+    // a dump is not tied to a chain, so it never reorgs
+    fn block_hash(&self, _client: &Client, _block_number: u64) -> Result<Option<H256>, Error> {
+        Ok(None)
+    }
+
+    // every announcement of a dump is final
+    fn confirmations(&self, _requested: u64) -> u64 {
+        0
+    }
+
+    // a dump holds past announcements only,
+    // so it is always scanned in full
+    fn start_block(&self, _client: &Client, _confirmations: u64) -> Result<u64, Error> {
+        Ok(0)
+    }
+}
+
+impl DumpRecord {
+    fn to_transaction(&self) -> Result<RsTxTransaction, Error> {
+        let stealth_address = match &self.stealth_address {
+            Some(address) if !address.is_empty() => {
+                Some(H160::from(<[u8; 20]>::from_hex(strip_hex_prefix(address))?))
+            },
+            _ => None
+        };
+
+        let rs_tx = RsTxTransaction {
+            id: self.id.clone(),
+            block: U64::from(self.block),
+            scheme: self.scheme,
+            nonce_point: String::from(strip_hex_prefix(&self.nonce_point)),
+            encrypted_recipient: hex::decode(strip_hex_prefix(&self.encrypted_recipient))?,
+            stealth_address: stealth_address,
+            metadata: hex::decode(strip_hex_prefix(&self.metadata))?
+        };
+
+        Ok(rs_tx)
+    }
+}
+
+fn read_jsonl(path: &PathBuf) -> Result<Vec<DumpRecord>, Error> {
+    let reader = BufReader::new(File::open(path)?);
+
+    let mut records = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(records)
+}
+
+fn read_csv(path: &PathBuf) -> Result<Vec<DumpRecord>, Error> {
+    let mut reader = csv::Reader::from_path(path)?;
+
+    let mut records = vec![];
+    for record in reader.deserialize() {
+        records.push(record?);
+    }
+
+    Ok(records)
+}
+
+fn strip_hex_prefix(s: &str) -> &str {
+    s.trim_start_matches("0x")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_dump_source() {
+        let dump_dir = tempfile::tempdir().unwrap();
+
+        let jsonl_path = dump_dir.path().join("announcements.jsonl");
+        let mut jsonl_file = File::create(&jsonl_path).unwrap();
+        writeln!(jsonl_file, r#"{{"id":"0x02","block":12,"scheme":"eip5564","nonce_point":"0x02aa","stealth_address":"0x55649e01b5df198d18d95b5cc5051630cfd45564","metadata":"0x7f"}}"#).unwrap();
+        writeln!(jsonl_file, r#"{{"id":"0x01","block":10,"nonce_point":"03bb","encrypted_recipient":"0x01ff"}}"#).unwrap();
+
+        let csv_path = dump_dir.path().join("announcements.csv");
+        let mut csv_file = File::create(&csv_path).unwrap();
+        writeln!(csv_file, "id,block,scheme,nonce_point,encrypted_recipient,stealth_address,metadata").unwrap();
+        writeln!(csv_file, "0x01,10,rstx,03bb,0x01ff,,").unwrap();
+        writeln!(csv_file, "0x02,12,eip5564,0x02aa,,0x55649e01b5df198d18d95b5cc5051630cfd45564,0x7f").unwrap();
+
+        // a dump never uses the client
+        let client = Client::new().unwrap();
+        for path in vec![jsonl_path, csv_path] {
+            let source = DumpSource::new(path).unwrap();
+            let txs = source.announcements(&client, 0, 12).unwrap();
+            assert!(txs.len() == 2);
+            assert!(txs[0].id == "0x01" && txs[0].scheme == StealthScheme::RsTx);
+            assert!(txs[0].encrypted_recipient == vec![0x01, 0xff]);
+            assert!(txs[1].nonce_point == "02aa" && txs[1].metadata == vec![0x7f]);
+            assert!(txs[1].stealth_address.is_some());
+            assert!(source.announcements(&client, 10, 12).unwrap().len() == 1);
+            assert!(source.announcements(&client, 0, 11).unwrap().len() == 1);
+            assert!(source.head(&client).unwrap() == 12);
+            assert!(source.confirmations(12) == 0 && source.start_block(&client, 12).unwrap() == 0);
+        }
+    }
+}
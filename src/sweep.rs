@@ -0,0 +1,234 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ethereum_tx_sign::RawTransaction;
+
+use rand::Rng;
+
+use serde::{Deserialize, Serialize};
+
+use web3::futures::Future;
+use web3::types::{Bytes, H160, H256, U256};
+
+use crate::client::Client;
+use crate::errors::Error;
+use crate::index::Announcement;
+use crate::key;
+use crate::payments;
+use crate::utils::{convert_h256, unix_timestamp};
+
+// gas of a plain ether transfer
+const SWEEP_GAS: u64 = 21000;
+
+// delay before a payment not worth
+// its sweep fee is checked again
+const FEE_RETRY_SECS: u64 = 300;
+
+/// Auto-sweep policy of a master key
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SweepPolicy {
+    /// Address the payments are swept to
+    pub destination: String,
+    /// Confirmations of the announcement before sweeping
+    #[serde(default)]
+    pub confirmations: u64,
+    /// Payments below this value (in wei) are left alone
+    #[serde(default)]
+    pub min_value: Option<String>,
+    /// Upper bound of the random delay before sweeping,
+    /// so that sweeps are not linked to payments by timing
+    #[serde(default)]
+    pub max_delay_secs: u64,
+}
+
+/// Payment waiting to be swept
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingSweep {
+    pub stealth_address: H160,
+    pub announcement: Announcement,
+    /// Unix timestamp before which it is not swept
+    pub not_before: u64,
+}
+
+/// Outcome of a due sweep
+#[derive(Debug, PartialEq)]
+enum Swept {
+    /// Sent, with its tx hash
+    Sent(H256),
+    /// Below the minimum value of the policy, left alone
+    BelowMinValue,
+    /// Not worth the fee (yet), retried later
+    BelowFee,
+}
+
+impl SweepPolicy {
+    pub fn destination(&self) -> Result<H160, Error> {
+        let destination = H160::from_str(self.destination.trim_start_matches("0x"))
+            .map_err(|_| Error::Custom(format!("Invalid sweep destination: {}", self.destination)))?;
+
+        Ok(destination)
+    }
+
+    pub fn min_value(&self) -> Result<U256, Error> {
+        match &self.min_value {
+            Some(min_value) => Ok(U256::from_dec_str(min_value)?),
+            None => Ok(U256::zero())
+        }
+    }
+
+    /// Queues the sweep of a payment, after a random delay
+    pub fn schedule(&self, stealth_address: H160, announcement: &Announcement) -> PendingSweep {
+        let delay = match self.max_delay_secs {
+            0 => 0,
+            max_delay_secs => rand::thread_rng().gen_range(0, max_delay_secs + 1)
+        };
+
+        PendingSweep {
+            stealth_address: stealth_address,
+            announcement: announcement.clone(),
+            not_before: unix_timestamp() + delay
+        }
+    }
+}
+
+impl PendingSweep {
+    /// Whether the announcement has enough confirmations
+    /// at `head`, and the random delay has passed at `now`
+    pub fn is_due(&self, confirmations: u64, head: u64, now: u64) -> bool {
+        self.announcement.block + confirmations <= head && self.not_before <= now
+    }
+}
+
+/// Sweeps the payments that are due, returns the ones still to sweep.
+/// Failed sweeps stay queued and are retried on the next call
+pub fn sweep_due(
+    client: &Client,
+    storage_dir: &PathBuf,
+    passphrase: &str,
+    policy: &SweepPolicy,
+    sweeps: Vec<PendingSweep>
+) -> Vec<PendingSweep> {
+    let head = match client.web3.eth().block_number().wait() {
+        Ok(head) => head.as_u64(),
+        Err(e) => {
+            eprintln!("[sweep] Error fetching the head: {:?}", e);
+            return sweeps;
+        }
+    };
+    let now = unix_timestamp();
+
+    let mut remaining = vec![];
+    for mut pending_sweep in sweeps {
+        if !pending_sweep.is_due(policy.confirmations, head, now) {
+            remaining.push(pending_sweep);
+            continue;
+        }
+
+        match sweep(&client, &storage_dir, &passphrase, &policy, &pending_sweep) {
+            Ok(Swept::Sent(tx_hash)) => {
+                println!("[sweep] Swept {:?}, tx hash: {:?}", pending_sweep.stealth_address, tx_hash);
+                let stealth_address = format!("{:?}", pending_sweep.stealth_address);
+                if let Err(e) = payments::mark_swept(&storage_dir, &stealth_address, &format!("{:?}", tx_hash)) {
+                    eprintln!("[sweep] Error recording the sweep: {:?}", e);
+                }
+            },
+            Ok(Swept::BelowMinValue) => println!("[sweep] Nothing to sweep from {:?}", pending_sweep.stealth_address),
+            Ok(Swept::BelowFee) => {
+                // the payment may not be funded yet, or gas be
+                // priced over it for a while, so it stays queued
+                println!("[sweep] Balance of {:?} below the sweep fee, retrying later", pending_sweep.stealth_address);
+                pending_sweep.not_before = now + FEE_RETRY_SECS;
+                remaining.push(pending_sweep);
+            },
+            Err(e) => {
+                eprintln!("[sweep] Error sweeping {:?}: {:?}", pending_sweep.stealth_address, e);
+                remaining.push(pending_sweep);
+            }
+        }
+    }
+
+    remaining
+}
+
+/// Sends the full balance minus gas of the stealth key to the
+/// destination, unless the balance is below the threshold or the fee
+fn sweep(
+    client: &Client,
+    storage_dir: &PathBuf,
+    passphrase: &str,
+    policy: &SweepPolicy,
+    pending_sweep: &PendingSweep
+) -> Result<Swept, Error> {
+    let stealth_address = pending_sweep.stealth_address;
+    let balance = client.web3.eth().balance(stealth_address, None).wait()?;
+    let gas_price = client.web3.eth().gas_price().wait()?;
+    let fee = gas_price * U256::from(SWEEP_GAS);
+    if balance <= fee {
+        return Ok(Swept::BelowFee);
+    }
+    if balance < policy.min_value()? {
+        return Ok(Swept::BelowMinValue);
+    }
+
+    // the stealth key was stored when the payment was claimed
+    let stealth_keypair = key::load(&storage_dir, &format!("{:?}", stealth_address), &passphrase)?;
+    let tx = RawTransaction {
+        nonce: client.web3.eth().transaction_count(stealth_address, None).wait()?,
+        to: Some(policy.destination()?),
+        value: balance - fee,
+        gas_price: gas_price,
+        gas: U256::from(SWEEP_GAS),
+        data: Vec::new()
+    };
+    let chain_id = client.web3.eth().chain_id().wait()?.as_u64();
+    let signed_tx = tx.sign(&convert_h256(stealth_keypair.secret()), &chain_id);
+    let tx_hash = client.web3.eth().send_raw_transaction(Bytes::from(signed_tx)).wait()?;
+
+    Ok(Swept::Sent(tx_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(confirmations: u64, max_delay_secs: u64) -> SweepPolicy {
+        SweepPolicy {
+            destination: String::from("0x55649e01b5df198d18d95b5cc5051630cfd45564"),
+            confirmations: confirmations,
+            min_value: None,
+            max_delay_secs: max_delay_secs
+        }
+    }
+
+    #[test]
+    fn test_schedule() {
+        let announcement = Announcement { tx_hash: String::from("0xabcd"), block: 42 };
+        let stealth_address = H160::from_low_u64_be(1);
+
+        let before = unix_timestamp();
+        let pending_sweep = policy(0, 0).schedule(stealth_address, &announcement);
+        assert!(pending_sweep.stealth_address == stealth_address && pending_sweep.announcement == announcement);
+        assert!(pending_sweep.not_before >= before && pending_sweep.not_before <= unix_timestamp());
+
+        for _ in 0..20 {
+            let before = unix_timestamp();
+            let pending_sweep = policy(0, 60).schedule(stealth_address, &announcement);
+            assert!(pending_sweep.not_before >= before && pending_sweep.not_before <= unix_timestamp() + 60);
+        }
+    }
+
+    #[test]
+    fn test_is_due() {
+        let pending_sweep = PendingSweep {
+            stealth_address: H160::from_low_u64_be(1),
+            announcement: Announcement { tx_hash: String::from("0xabcd"), block: 42 },
+            not_before: 1000
+        };
+
+        // both the confirmations and the delay are needed
+        assert!(pending_sweep.is_due(3, 45, 1000));
+        assert!(!pending_sweep.is_due(3, 44, 1000));
+        assert!(!pending_sweep.is_due(3, 45, 999));
+        assert!(pending_sweep.is_due(0, 42, 2000));
+    }
+}
@@ -1,10 +1,9 @@
 use std::path::PathBuf;
 use std::str::FromStr;
 
-use parity_crypto::Keccak256;
 use parity_crypto::publickey::ec_math_utils;
 use parity_crypto::publickey::public_to_address;
-use parity_crypto::publickey::{Address, KeyPair, Generator, Random, Secret};
+use parity_crypto::publickey::{Address, KeyPair, Generator, Random};
 
 use aes_gcm::Aes256Gcm;
 use aead::{Aead, NewAead, generic_array::GenericArray};
@@ -15,6 +14,9 @@ use ethereum_tx_sign::RawTransaction;
 
 use hex::FromHex;
 
+use rand::RngCore;
+use rand::rngs::OsRng;
+
 use web3::Web3;
 use web3::contract::tokens::Tokenize;
 use web3::futures::Future;
@@ -23,11 +25,17 @@ use web3::types::{Bytes, H160, H256, U256};
 
 use crate::errors::Error;
 use crate::key;
+use crate::meta_address::StealthMetaAddress;
+use crate::payload::RsTxPayload;
+use crate::payments;
+use crate::scheme::{eip5564_metadata, StealthScheme};
 use crate::utils::{convert_h160, convert_h256};
 
-static RS_TX_CONTRACT_ADDRESS: &'static str = "a3b67474A27Ba4bb28eE22e5f1C4529c07A45287";
+pub static RS_TX_CONTRACT_ADDRESS: &'static str = "a3b67474A27Ba4bb28eE22e5f1C4529c07A45287";
+pub static ERC5564_ANNOUNCER_ADDRESS: &'static str = "55649E01B5Df198D18D95b5cc5051630cfD45564";
 
 pub struct Transfer {
+    pub scheme: StealthScheme,
     pub nonce_point: String,
     pub tx1_hash: H256,
     pub tx2_hash: H256,
@@ -36,6 +44,9 @@ pub struct Transfer {
 pub fn transfer(
     from_path: &PathBuf,
     from_address: &str,
+    passphrase: &str,
+    chain: &str,
+    scheme: StealthScheme,
     to: &str,
     value: &str
 ) -> Result<Transfer, Error> {
@@ -43,9 +54,15 @@ pub fn transfer(
     let (_eloop, transport) = web3::transports::Http::new("http://127.0.0.1:8545").unwrap();
     let web3 = web3::Web3::new(transport);
 
-    // parse recipient's public key and address
-    // create a copy to use for EC math
-    let (public_key, address) = key::public_key_from_str(to)?;
+    // parse recipient's spend and scan public keys
+    // the spend address is what the recipient looks for
+    let meta_address = StealthMetaAddress::from_str(to)?;
+    if meta_address.chain != chain {
+        return Err(Error::Custom(format!("Meta-address is for chain {}, not {}", meta_address.chain, chain)));
+    }
+    let spend_public_key = meta_address.spend_public;
+    let scan_public_key = meta_address.scan_public;
+    let address = public_to_address(&spend_public_key);
 
     // generate random nonce and calculate nonce point
     // nonce point is then shared with the recipient
@@ -54,36 +71,53 @@ pub fn transfer(
     ec_math_utils::public_mul_secret(&mut nonce_point, &nonce)?;
     let secp_nonce_point = key::to_secp256k1_public(&nonce_point)?;
 
-    // generate ECDH shared secret
-    // this secret can also be generated by Recipient
-    // with the knowledge of the above `nonce_point`
-    let mut ecdh_shared_secret = public_key.clone();
-    ec_math_utils::public_mul_secret(&mut ecdh_shared_secret, &nonce)?;
-    let ecdh_shared_secret_hash = ecdh_shared_secret.keccak256();
-
-    // get the secret number from ecdh secret's hash
-    // calculate recipient's address
-    let secret_number = Secret::from_str(&hex::encode(ecdh_shared_secret_hash))?;
-    let mut recipient_public_key = ec_math_utils::generation_point();
-    ec_math_utils::public_mul_secret(&mut recipient_public_key, &secret_number)?;
-    ec_math_utils::public_add(&mut recipient_public_key, &public_key)?;
+    // generate ECDH shared secret against the scan key
+    // this secret can also be generated by Recipient's scan
+    // key with the knowledge of the above `nonce_point`
+    let ecdh_shared_secret_hash = scheme.shared_secret(&scan_public_key, &nonce)?;
+
+    // calculate recipient's address from the spend key
+    let recipient_public_key = key::stealth_public(&spend_public_key, &ecdh_shared_secret_hash)?;
     let recipient_address = public_to_address(&recipient_public_key);
     println!("recipient address = {:?}", recipient_address);
 
     // form signed transactions for
     // both Transfer and Broadcasting nonce
-    let sender_keypair = key::load(&from_path, &from_address)?;
+    let sender_keypair = key::load(&from_path, &from_address, &passphrase)?;
     let transfer_nonce = web3.eth().transaction_count(convert_h160(sender_keypair.address()), None).wait().unwrap();
     let broadcast_nonce = transfer_nonce + 1;
     let transfer_signed_tx = transfer_tx(&web3, &sender_keypair, transfer_nonce, &recipient_address, value)?;
-    let broadcast_signed_tx = broadcast_tx(&web3, &sender_keypair, broadcast_nonce, &secp_nonce_point, &ecdh_shared_secret_hash, &address)?;
+    let broadcast_signed_tx = match scheme {
+        StealthScheme::RsTx => broadcast_tx(&web3, &sender_keypair, broadcast_nonce, &secp_nonce_point, &ecdh_shared_secret_hash, &address)?,
+        StealthScheme::Eip5564 => {
+            let view_tag = scheme.view_tag(&ecdh_shared_secret_hash);
+            let metadata = eip5564_metadata(view_tag, U256::from_dec_str(value)?);
+            announce_tx(&web3, &sender_keypair, broadcast_nonce, &secp_nonce_point, &recipient_address, metadata)?
+        }
+    };
 
     // broadcast both transactions
     let transfer_tx_hash = web3.eth().send_raw_transaction(Bytes::from(transfer_signed_tx)).wait().unwrap();
     let broadcast_tx_hash = web3.eth().send_raw_transaction(Bytes::from(broadcast_signed_tx)).wait().unwrap();
 
+    // record it in the payments database, the
+    // transfer is sent already if that fails
+    let outgoing = payments::OutgoingTransfer {
+        from_address: from_address.to_string(),
+        recipient: to.to_string(),
+        stealth_address: format!("{:?}", recipient_address),
+        amount: value.to_string(),
+        funding_tx: format!("{:?}", transfer_tx_hash),
+        announcement_tx: format!("{:?}", broadcast_tx_hash),
+        scheme: scheme.to_string()
+    };
+    if let Err(e) = payments::record_transfer(&from_path, &outgoing) {
+        eprintln!("[transfer] Error recording the transfer: {:?}", e);
+    }
+
     // return the Transfer object
     let transfer = Transfer {
+        scheme: scheme,
         nonce_point: secp_nonce_point.to_string(),
         tx1_hash: transfer_tx_hash,
         tx2_hash: broadcast_tx_hash
@@ -132,22 +166,23 @@ fn broadcast_tx(
     let abi = ethabi::Contract::load(json_abi)?;
 
     // encrypt the recipient address
+    // with a random encryption nonce
     let key = GenericArray::clone_from_slice(shared_secret);
     let aead = Aes256Gcm::new(key);
-    let mut tx_nonce_slice = [0u8; 32];
-    tx_nonce.to_big_endian(&mut tx_nonce_slice);
-    let popped_tx_nonce = {
-		let mut temp = [0u8; 12];
-		(&mut temp[0..12]).copy_from_slice(&tx_nonce_slice[0..12]);
-		temp
-	};
-    let encryption_nonce = GenericArray::from_slice(&popped_tx_nonce);
+    let mut random_nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut random_nonce);
+    let encryption_nonce = GenericArray::from_slice(&random_nonce);
     let encrypted_recipient = aead.encrypt(encryption_nonce, recipient_address.as_ref())?;
     println!("encrypted recipient = {:?}", encrypted_recipient);
 
+    // the versioned payload carries the view tag and the
+    // nonce, so scanners decrypt it without any RPC call
+    let view_tag = StealthScheme::RsTx.view_tag(shared_secret);
+    let payload = RsTxPayload::new(view_tag, random_nonce, encrypted_recipient);
+
     // get params for transaction
     let nonce_point: Vec<u8> = secp_nonce_point.serialize().iter().cloned().collect();
-    let params = (Bytes::from(nonce_point), Bytes::from(encrypted_recipient));
+    let params = (Bytes::from(nonce_point), Bytes::from(payload.encode()));
     let contract_address = <[u8; 20]>::from_hex(RS_TX_CONTRACT_ADDRESS)?;
 
     // encode function call params
@@ -173,3 +208,46 @@ fn broadcast_tx(
         Err(e) => Err(Error::Ethabi(e))
     }
 }
+
+fn announce_tx(
+    web3: &Web3<Http>,
+    from: &KeyPair,
+    tx_nonce: U256,
+    secp_nonce_point: &PublicKey,
+    stealth_address: &Address,
+    metadata: Vec<u8>
+) -> Result<Vec<u8>, Error> {
+    // form contract abi
+    let json_abi: &[u8] = include_bytes!("contracts/ERC5564Announcer.abi");
+    let abi = ethabi::Contract::load(json_abi)?;
+
+    // get params for transaction, the nonce
+    // point is the ephemeral public key
+    let scheme_id = U256::from(StealthScheme::Eip5564.id().unwrap_or(1));
+    let ephemeral_public_key: Vec<u8> = secp_nonce_point.serialize().iter().cloned().collect();
+    let params = (scheme_id, convert_h160(*stealth_address), Bytes::from(ephemeral_public_key), Bytes::from(metadata));
+    let contract_address = <[u8; 20]>::from_hex(ERC5564_ANNOUNCER_ADDRESS)?;
+
+    // encode function call params
+    // sign tx and return the raw signed tx
+    match abi.function("announce")
+       .and_then(|function| function.encode_input(&params.into_tokens()))
+       .and_then(|data| {
+           let tx = RawTransaction {
+               nonce: tx_nonce,
+               to: Some(H160::from(contract_address)),
+               value: U256::from(0),
+               gas_price: U256::from(1000000000),
+               gas: U256::from(1000000),
+               data: data.into()
+           };
+
+           let chain_id = web3.eth().chain_id().wait().unwrap().as_u64();
+           let signed_tx = tx.sign(&convert_h256(from.secret()), &chain_id);
+
+           Ok(signed_tx)
+       }) {
+        Ok(signed_tx) => Ok(signed_tx),
+        Err(e) => Err(Error::Ethabi(e))
+    }
+}
@@ -1,6 +1,8 @@
 use std::convert::TryFrom;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use hex::FromHex;
 use parity_crypto::publickey::Secret;
 use web3::types::{H160, H256};
 
@@ -20,8 +22,38 @@ pub fn convert_u64_i64(v: u64) -> Option<i64> {
     i64::try_from(v).ok()
 }
 
-pub fn convert_str_h256(v: &str) -> Result<H256, Error> {
-    let u = &v[2..66];
-    let u_hex = <[u8; 32]>::from_hex(u)?;
-    Ok(H256::from(u_hex))
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn read_passphrase(password_file: &Option<PathBuf>, confirm: bool) -> Result<String, Error> {
+    let passphrase = read_secret(&password_file, "Passphrase: ")?;
+    if confirm && password_file.is_none() {
+        let repeated = rpassword::read_password_from_tty(Some("Repeat passphrase: "))?;
+        if passphrase != repeated {
+            return Err(Error::Custom(String::from("Passphrases do not match")));
+        }
+    }
+
+    Ok(passphrase)
+}
+
+pub fn read_mnemonic(mnemonic_file: &Option<PathBuf>) -> Result<String, Error> {
+    let mnemonic = read_secret(&mnemonic_file, "Mnemonic: ")?;
+    Ok(mnemonic.trim().to_string())
+}
+
+fn read_secret(file: &Option<PathBuf>, prompt: &str) -> Result<String, Error> {
+    // read from the file if provided
+    // otherwise prompt for it on the terminal
+    if let Some(path) = file {
+        let secret = fs::read_to_string(path)?;
+        return Ok(String::from(secret.trim_end_matches(|c| c == '\n' || c == '\r')));
+    }
+
+    let secret = rpassword::read_password_from_tty(Some(prompt))?;
+    Ok(secret)
 }